@@ -47,12 +47,12 @@ fn main() {
     let game = Game::new(100);
 
     {
-        let mut p1 = game.add_player("Sune");
+        let p1 = game.add_player("Sune");
         let p2 = game.add_player("Berra");
 
-        let _ = p1.friends.insert(p2.weak()).unwrap();
-        p2.friends.insert(p1.weak()).unwrap();
-        assert_eq!(p1.game, p2.game);
+        let _ = p1.borrow().friends.insert(p2.weak()).unwrap();
+        p2.borrow().friends.insert(p1.weak()).unwrap();
+        assert_eq!(p1.borrow().game, p2.borrow().game);
         //assert_eq!(p1.friends.get(index), Some(p2));
 
         p1.borrow_mut().name += " Sunesson";
@@ -60,11 +60,12 @@ fn main() {
     }
 
     for _ in 0..2 {
-        for mut p in game.players.iter() {
-            println!("{}, {}", p.name, p.health);
+        for p in game.players.iter() {
+            println!("{}, {}", p.borrow().name, p.borrow().health);
             p.borrow_mut().health = 2;
 
-            for f in p.friends.iter_ref() {
+            for f in p.borrow().friends.iter_ref() {
+                let f = f.borrow();
                 println!("  {}, {}", f.name, f.health);
             }
 
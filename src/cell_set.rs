@@ -11,6 +11,16 @@ pub struct CellSet<T, A> {
     _phantom: PhantomData<fn(T) -> T>,
 }
 
+/// Statistics returned by [`CellSet::stats`]. Elements are kept together at the end of the slots, so unlike
+/// [`PoolStats`](crate::rc_pool::PoolStats) there is no free list or high-water mark to report.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct CellSetStats {
+    pub len:      usize,
+    /// Number of empty slots, an insertion fails when it's zero
+    pub free:     usize,
+    pub capacity: usize,
+}
+
 pub type VecCellSet<T> = CellSet<T, Vec<Slot<T>>>;
 pub type ArrayCellSet<T, const CAP: usize> = CellSet<T, [Slot<T>; CAP]>;
 
@@ -47,6 +57,15 @@ impl<T, A: AsRef<[Slot<T>]>> CellSet<T, A> {
     #[must_use]
     pub fn is_empty(&self) -> bool { self.first.get() == self.capacity() }
 
+    #[must_use]
+    pub fn stats(&self) -> CellSetStats {
+        CellSetStats {
+            len:      self.len(),
+            free:     self.first.get(),
+            capacity: self.capacity(),
+        }
+    }
+
     pub fn remove(&self, index: usize) -> T {
         let first = self.first.get();
         let slot = &self.slots.as_ref()[first + index];
@@ -163,3 +182,14 @@ impl<T, A: AsRef<[Slot<T>]>> Debug for CellSet<T, A> {
 impl<T, A: AsRef<[Slot<T>]> + Default> Default for CellSet<T, A> {
     fn default() -> Self { Self::new(A::default()) }
 }
+
+#[test]
+fn test_stats() {
+    let set = VecCellSet::new_vec(4);
+    (0..3).for_each(|i| _ = set.insert(i));
+    let _ = set.remove(0);
+
+    let stats = set.stats();
+    assert_eq!((stats.len, stats.free, stats.capacity), (2, 2, 4));
+    assert_eq!(stats.len + stats.free, stats.capacity);
+}
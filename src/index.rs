@@ -1,24 +1,31 @@
-pub trait Index {
+pub trait Index: Copy + Eq + std::hash::Hash {
+    /// Largest value representable by the index type
+    const MAX: usize;
+
     fn to_usize(self) -> usize;
     fn from_usize(size: usize) -> Self;
 }
 
 impl Index for usize {
+    const MAX: usize = usize::MAX;
     fn to_usize(self) -> usize { self }
     fn from_usize(size: usize) -> Self { size }
 }
 
 impl Index for u8 {
+    const MAX: usize = u8::MAX as usize;
     fn to_usize(self) -> usize { self as usize }
     fn from_usize(size: usize) -> Self { size as Self }
 }
 
 impl Index for u16 {
+    const MAX: usize = u16::MAX as usize;
     fn to_usize(self) -> usize { self as usize }
     fn from_usize(size: usize) -> Self { size as Self }
 }
 
 impl Index for u32 {
+    const MAX: usize = u32::MAX as usize;
     fn to_usize(self) -> usize { self as usize }
     fn from_usize(size: usize) -> Self { size as Self }
 }
@@ -1,52 +1,245 @@
 use crate::cell_trait::CellTrait;
-use crate::clear::Clear;
+use crate::clear::{Clear, ClearMut};
+use crate::index::Index;
 use crate::refs::WeakRefTrait;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::ops::DerefMut;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{
-    cell::{Cell, UnsafeCell},
-    mem::MaybeUninit,
+    cell::{Cell, RefCell, UnsafeCell},
     ops::Deref,
 };
 
-const MUT_REF_COUNT: u32 = u32::MAX;
+const MUT_BORROW: i32 = -1;
 
+/// End of the insertion order list, also used for the links of slots that aren't in the list
+const NO_SLOT: i32 = -1;
+
+/// Version of a slot whose element has been removed while strong references to it were still alive
+const REMOVED_VERSION: i32 = i32::MIN;
+
+/// Version of a slot that is never reused because its generation would wrap around, which could make stale
+/// references valid again
+const RETIRED_VERSION: i32 = i32::MIN + 1;
+
+/// Reference count of a slot, in debug builds dropping a slot that still has references (for example leaked with
+/// `std::mem::forget`) panics.
+/// It's kept separate from the element type so that [`RcPool`] doesn't need a `Drop` impl, which would prevent
+/// elements from holding references into their own pool.
+#[derive(Default)]
+struct RefCount(Cell<u32>);
+
+impl Deref for RefCount {
+    type Target = Cell<u32>;
+    fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl Drop for RefCount {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && self.0.get() > 0 && !std::thread::panicking() {
+            panic!("Dropping pool element with {} outstanding references!", self.0.get())
+        }
+    }
+}
+
+/// The header comes first so that references with the element type erased, [`DynStrongRef`] and [`DynWeakRef`], can
+/// point to it and still reach the element
+#[repr(C)]
 pub struct Slot<T> {
-    elem:      UnsafeCell<MaybeUninit<T>>,
-    version:   Cell<i32>,
-    ref_count: Cell<u32>,
+    header: SlotHeader,
+    elem:   UnsafeCell<Option<T>>,
+}
+
+/// Part of a [`Slot`] that doesn't depend on the element type. It takes 32 bytes, of which the links for
+/// [`RcPool::with_insertion_order`] and the use time for [`OverflowPolicy::EvictLru`] are needed only by pools that
+/// use those.
+#[derive(Default)]
+pub struct SlotHeader {
+    version:    Cell<i32>,
+    generation: Cell<i32>,
+    ref_count:  RefCount,
+    borrows:    Cell<i32>,
+    index:      Cell<i32>,
+    used:       Cell<u32>,
+    prev:       Cell<i32>,
+    next:       Cell<i32>,
+}
+
+impl SlotHeader {
+    /// Key of the element in the slot, also after it has been removed as long as the slot hasn't been freed
+    #[must_use]
+    fn key<I: Index, G: Index>(&self) -> PoolKey<I, G> { PoolKey::new(self.index.get(), self.generation.get()) }
+
+    /// Returns true if a weak reference with `version` still refers to the element in the slot
+    #[must_use]
+    fn is_valid(&self, version: i32) -> bool { version >= 0 && version == self.version.get() }
+
+    /// Adds a strong reference and marks the element as used
+    fn acquire(&self, pool: &PoolState) {
+        self.used.set(pool.tick());
+        self.ref_count.add(1);
+    }
+
+    /// Adds a shared borrow, returns false if the element is mutably borrowed
+    #[must_use]
+    fn try_borrow(&self) -> bool {
+        let ok = self.borrows.get() != MUT_BORROW;
+
+        if ok {
+            self.borrows.add(1);
+        }
+
+        ok
+    }
+
+    /// Marks the element as mutably borrowed, returns false if it's already borrowed
+    #[must_use]
+    fn try_borrow_mut(&self) -> bool {
+        let ok = self.borrows.get() == 0;
+
+        if ok {
+            self.borrows.set(MUT_BORROW);
+        }
+
+        ok
+    }
+
+    fn release_borrow(&self) { self.borrows.sub(1) }
+    fn release_borrow_mut(&self) { self.borrows.set(0) }
+}
+
+impl<T> Deref for Slot<T> {
+    type Target = SlotHeader;
+    fn deref(&self) -> &SlotHeader { &self.header }
 }
 
 impl<T> Slot<T> {
     #[must_use]
-    unsafe fn get(&self) -> &T { (*self.elem.get()).assume_init_ref() }
+    unsafe fn get(&self) -> &T { (*self.elem.get()).as_ref().unwrap_unchecked() }
 
     #[must_use]
     #[allow(clippy::mut_from_ref)]
-    unsafe fn get_mut(&self) -> &mut T { (*self.elem.get()).assume_init_mut() }
+    unsafe fn get_mut(&self) -> &mut T { (*self.elem.get()).as_mut().unwrap_unchecked() }
 
-    fn drop_elem(&self) {
+    /// Drops the element of a freed slot, or clears it and keeps it for the next insertion if the pool recycles
+    /// elements
+    fn drop_elem(&self, pool: &PoolState) {
         if self.ref_count.get() > 0 {
             panic!("Trying to remove item with references!")
         }
 
-        unsafe { (*self.elem.get()).assume_init_drop() };
+        // Move the element out before dropping it so no reference into the slot is alive while its destructor runs
+        let elem = unsafe { (*self.elem.get()).take() };
+
+        if let (Some(clear), Some(mut elem)) = (pool.recycle, elem) {
+            unsafe { clear((&mut elem as *mut T).cast()) };
+
+            // `clear` may have touched the pool, the slot can have been reused or retired in the meantime
+            let v = self.version.get();
+
+            if v < 0 && v != REMOVED_VERSION && v != RETIRED_VERSION && unsafe { (*self.elem.get()).is_none() } {
+                unsafe { *self.elem.get() = Some(elem) };
+            }
+        }
     }
 }
 
 impl<T> Default for Slot<T> {
     fn default() -> Self {
         Self {
-            elem:      UnsafeCell::new(MaybeUninit::uninit()),
-            version:   Default::default(),
-            ref_count: Default::default(),
+            header: Default::default(),
+            elem:   UnsafeCell::new(None),
+        }
+    }
+}
+
+/// Backing storage for the slots of an [`RcPool`]
+pub trait SlotStorage<T> {
+    fn capacity(&self) -> usize;
+    fn slot(&self, index: usize) -> Option<&Slot<T>>;
+
+    /// Adds more slots at the end of the storage without moving the existing ones, returns false if the storage
+    /// can't grow
+    fn grow(&self) -> bool { false }
+}
+
+impl<T, A: AsRef<[Slot<T>]>> SlotStorage<T> for A {
+    fn capacity(&self) -> usize { self.as_ref().len() }
+    fn slot(&self, index: usize) -> Option<&Slot<T>> { self.as_ref().get(index) }
+}
+
+/// Growable slot storage made of fixed size chunks, slots never move once allocated so outstanding references stay
+/// valid when the storage grows
+pub struct ChunkedSlots<T, const CHUNK: usize> {
+    chunks: UnsafeCell<Vec<Box<[Slot<T>]>>>,
+}
+
+impl<T, const CHUNK: usize> ChunkedSlots<T, CHUNK> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            chunks: Default::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_capacity(cap: usize) -> Self {
+        let s = Self::new();
+
+        while s.capacity() < cap {
+            s.grow();
         }
+
+        s
+    }
+
+    #[must_use]
+    fn chunks(&self) -> &Vec<Box<[Slot<T>]>> { unsafe { &*self.chunks.get() } }
+}
+
+impl<T, const CHUNK: usize> SlotStorage<T> for ChunkedSlots<T, CHUNK> {
+    fn capacity(&self) -> usize { self.chunks().len() * CHUNK }
+
+    fn slot(&self, index: usize) -> Option<&Slot<T>> {
+        self.chunks().get(index / CHUNK).map(|c| unsafe { c.get_unchecked(index % CHUNK) })
+    }
+
+    fn grow(&self) -> bool {
+        assert_ne!(CHUNK, 0);
+        let mut chunk = Vec::with_capacity(CHUNK);
+        chunk.resize_with(CHUNK, Slot::default);
+
+        // Only the chunk list is reallocated, the chunks themselves stay in place
+        unsafe { (*self.chunks.get()).push(chunk.into_boxed_slice()) };
+        true
     }
 }
 
+impl<T, const CHUNK: usize> Default for ChunkedSlots<T, CHUNK> {
+    fn default() -> Self { Self::new() }
+}
+
+/// Shared borrow of a pool element, any number of these can exist at the same time as long as there is no [`RefMut`]
+/// to the element
+pub struct Ref<'t, 'u, T> {
+    r: &'t StrongRef<'u, T>,
+}
+
+impl<'t, 'u, T> Deref for Ref<'t, 'u, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target { unsafe { self.r.slot.get() } }
+}
+
+impl<'t, 'u, T> Drop for Ref<'t, 'u, T> {
+    fn drop(&mut self) { self.r.slot.release_borrow() }
+}
+
+/// Exclusive borrow of a pool element
 pub struct RefMut<'t, 'u, T> {
-    r: &'t mut StrongRef<'u, T>,
+    r: &'t StrongRef<'u, T>,
 }
 
 impl<'t, 'u, T> Deref for RefMut<'t, 'u, T> {
@@ -58,49 +251,192 @@ impl<'t, 'u, T> DerefMut for RefMut<'t, 'u, T> {
     fn deref_mut(&mut self) -> &mut Self::Target { unsafe { self.r.slot.get_mut() } }
 }
 
+impl<'t, 'u, T> RefMut<'t, 'u, T> {
+    /// Creates an exclusive borrow of a part of the element, the element stays borrowed until the returned reference
+    /// is dropped
+    pub fn map<U: ?Sized>(mut orig: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedRefMut<'t, 'u, T, U> {
+        let value = f(&mut orig) as *mut U;
+        let r = orig.r;
+        std::mem::forget(orig);
+        MappedRefMut { r, value }
+    }
+}
+
 impl<'t, 'u, T> Drop for RefMut<'t, 'u, T> {
-    fn drop(&mut self) { self.r.slot.ref_count.set(1) }
+    fn drop(&mut self) { self.r.slot.release_borrow_mut() }
+}
+
+/// Exclusive borrow of a part of a pool element, created with [`RefMut::map`]
+pub struct MappedRefMut<'t, 'u, T, U: ?Sized> {
+    r:     &'t StrongRef<'u, T>,
+    value: *mut U,
+}
+
+impl<'t, 'u, T, U: ?Sized> Deref for MappedRefMut<'t, 'u, T, U> {
+    type Target = U;
+    fn deref(&self) -> &Self::Target { unsafe { &*self.value } }
+}
+
+impl<'t, 'u, T, U: ?Sized> DerefMut for MappedRefMut<'t, 'u, T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target { unsafe { &mut *self.value } }
+}
+
+impl<'t, 'u, T, U: ?Sized> Drop for MappedRefMut<'t, 'u, T, U> {
+    fn drop(&mut self) { self.r.slot.release_borrow_mut() }
+}
+
+/// Strong reference to a part of a pool element, created with [`StrongRef::map`]. It keeps the element alive and
+/// shared borrowed until it's dropped.
+pub struct MappedRef<'t, T, U: ?Sized> {
+    r:     StrongRef<'t, T>,
+    value: *const U,
+}
+
+impl<'t, T, U: ?Sized> Deref for MappedRef<'t, T, U> {
+    type Target = U;
+    fn deref(&self) -> &Self::Target { unsafe { &*self.value } }
+}
+
+impl<'t, T, U: ?Sized> Clone for MappedRef<'t, T, U> {
+    fn clone(&self) -> Self {
+        self.r.slot.borrows.add(1);
+
+        Self {
+            r:     self.r.clone(),
+            value: self.value,
+        }
+    }
+}
+
+impl<'t, T, U: ?Sized> Drop for MappedRef<'t, T, U> {
+    fn drop(&mut self) { self.r.slot.release_borrow() }
+}
+
+/// Weak reference to a part of a pool element, created with [`WeakRef::map`]
+pub struct MappedWeakRef<'t, T, U: ?Sized> {
+    r: WeakRef<'t, T>,
+    f: fn(&T) -> &U,
+}
+
+impl<'t, T, U: ?Sized> MappedWeakRef<'t, T, U> {
+    #[must_use]
+    pub fn get(&self) -> Option<MappedRef<'t, T, U>> { self.r.get().map(|r| r.map(self.f)) }
+
+    #[must_use]
+    pub fn is_valid(&self) -> bool { self.r.is_valid() }
 }
 
+impl<'t, T, U: ?Sized> Clone for MappedWeakRef<'t, T, U> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<'t, T, U: ?Sized> Copy for MappedWeakRef<'t, T, U> {}
+
 pub struct StrongRef<'t, T> {
     slot: &'t Slot<T>,
+    pool: &'t PoolState,
 }
 
 impl<'t, T> StrongRef<'t, T> {
-    fn new(slot: &'t Slot<T>) -> Self {
-        assert_ne!(slot.ref_count.get(), MUT_REF_COUNT);
+    fn new(slot: &'t Slot<T>, pool: &'t PoolState) -> Self {
+        slot.acquire(pool);
+        Self { slot, pool }
+    }
+
+    /// Creates a strong reference without marking the element as used
+    fn acquire(slot: &'t Slot<T>, pool: &'t PoolState) -> Self {
         slot.ref_count.add(1);
-        Self { slot }
+        Self { slot, pool }
     }
 
-    pub fn weak(&self) -> WeakRef<'t, T> { WeakRef::new(self.slot) }
+    pub fn weak(&self) -> WeakRef<'t, T> { WeakRef::new(self.slot, self.pool) }
+    pub fn key<I: Index, G: Index>(&self) -> PoolKey<I, G> { self.slot.key() }
+
     pub fn is_unique(&self) -> bool { self.slot.ref_count.get() == 1 }
 
-    pub fn borrow_mut<'u>(&'u mut self) -> RefMut<'u, 't, T> {
-        self.try_borrow_mut().expect("More than one strong reference!")
+    /// Index of the element's slot in the pool
+    pub fn index(&self) -> usize { self.slot.index.get() as usize }
+
+    /// Id of the pool the element belongs to
+    pub fn pool_id(&self) -> PoolId { self.pool.id }
+
+    pub fn borrow<'u>(&'u self) -> Ref<'u, 't, T> { self.try_borrow().expect("Element is mutably borrowed!") }
+
+    pub fn try_borrow<'u>(&'u self) -> Option<Ref<'u, 't, T>> { self.slot.try_borrow().then(|| Ref { r: self }) }
+
+    pub fn borrow_mut<'u>(&'u self) -> RefMut<'u, 't, T> {
+        self.try_borrow_mut().expect("Element is already borrowed!")
     }
 
-    pub fn try_borrow_mut<'u>(&'u mut self) -> Option<RefMut<'u, 't, T>> {
-        if self.is_unique() {
-            self.slot.ref_count.set(MUT_REF_COUNT);
-            Some(RefMut { r: self })
-        } else {
-            None
+    /// Returns an exclusive borrow of the element if there are no other borrows of it, regardless of how many strong
+    /// references there are
+    pub fn try_borrow_mut<'u>(&'u self) -> Option<RefMut<'u, 't, T>> {
+        self.slot.try_borrow_mut().then(|| RefMut { r: self })
+    }
+
+    /// Creates a strong reference to a part of the element, the element stays shared borrowed until the returned
+    /// reference is dropped
+    pub fn map<U: ?Sized>(self, f: impl FnOnce(&T) -> &U) -> MappedRef<'t, T, U> {
+        let value = f(&*self.borrow()) as *const U;
+        self.slot.borrows.add(1);
+        MappedRef { r: self, value }
+    }
+
+    /// Erases the element type, see [`DynStrongRef`]
+    #[must_use]
+    pub fn into_dyn<U: ?Sized>(self) -> DynStrongRef<'t, U>
+    where
+        T: AsDyn<U>,
+    {
+        let r = DynSlot::new(self.slot, self.pool);
+        std::mem::forget(self);
+        DynStrongRef { r }
+    }
+
+    /// Removes the element from the pool and returns it if this is the last strong reference to it, otherwise the
+    /// reference is dropped and `None` is returned. An element that has already been removed is returned too.
+    pub fn into_inner(self) -> Option<T> {
+        if !self.is_unique() {
+            return None;
         }
+
+        let (slot, pool) = (self.slot, self.pool);
+        std::mem::forget(self);
+        slot.ref_count.set(0);
+        pool.emit_release(slot);
+        pool.free(slot);
+        Some(unsafe { (*slot.elem.get()).take().unwrap_unchecked() })
     }
 }
 
 impl<'t, T> Clone for StrongRef<'t, T> {
-    fn clone(&self) -> Self { Self::new(self.slot) }
+    fn clone(&self) -> Self { Self::new(self.slot, self.pool) }
 }
 
-impl<'t, T> Deref for StrongRef<'t, T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target { unsafe { self.slot.get() } }
+impl<'t, T> Drop for StrongRef<'t, T> {
+    fn drop(&mut self) {
+        self.slot.ref_count.sub(1);
+
+        if self.slot.ref_count.get() == 0 && (self.pool.auto_remove || self.slot.version.get() == REMOVED_VERSION) {
+            self.pool.emit_release(self.slot);
+            self.pool.free(self.slot);
+            self.slot.drop_elem(self.pool);
+        }
+    }
 }
 
-impl<'t, T> Drop for StrongRef<'t, T> {
-    fn drop(&mut self) { self.slot.ref_count.sub(1) }
+impl<'t, T: Debug> Debug for StrongRef<'t, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("StrongRef");
+        d.field("index", &self.index()).field("version", &self.slot.version.get());
+
+        match self.try_borrow() {
+            Some(r) => d.field("value", &*r),
+            None => d.field("value", &format_args!("<borrowed>")),
+        };
+
+        d.finish()
+    }
 }
 
 impl<'t, T> TryFrom<WeakRef<'t, T>> for StrongRef<'t, T> {
@@ -112,20 +448,25 @@ impl<'t, T> TryFrom<WeakRef<'t, T>> for StrongRef<'t, T> {
 }
 
 impl<'t, T> crate::refs::StrongRefTrait for StrongRef<'t, T> {
+    type Target = T;
     type Weak = WeakRef<'t, T>;
     fn downgrade(&self) -> WeakRef<'t, T> { self.weak() }
 }
 
+/// Weak reference to a pool element. It keeps a pointer to the pool so that it can be upgraded without it, which
+/// makes it three words large. Use [`PoolKey`] with small index and generation types where compact handles matter.
 pub struct WeakRef<'t, T> {
     slot:    &'t Slot<T>,
+    pool:    &'t PoolState,
     version: i32,
 }
 
 impl<'t, T> WeakRef<'t, T> {
     #[must_use]
-    fn new(slot: &'t Slot<T>) -> Self {
+    fn new(slot: &'t Slot<T>, pool: &'t PoolState) -> Self {
         Self {
             slot,
+            pool,
             version: slot.version.get(),
         }
     }
@@ -133,11 +474,37 @@ impl<'t, T> WeakRef<'t, T> {
     #[must_use]
     pub fn get(&self) -> Option<StrongRef<'t, T>> {
         if self.is_valid() {
-            Some(StrongRef::new(self.slot))
+            Some(StrongRef::new(self.slot, self.pool))
         } else {
             None
         }
     }
+
+    #[must_use]
+    pub fn key<I: Index, G: Index>(&self) -> PoolKey<I, G> { PoolKey::new(self.slot.index.get(), self.version) }
+
+    /// Index of the element's slot in the pool
+    #[must_use]
+    pub fn index(&self) -> usize { self.slot.index.get() as usize }
+
+    /// Id of the pool the element belongs to
+    #[must_use]
+    pub fn pool_id(&self) -> PoolId { self.pool.id }
+
+    #[must_use]
+    pub fn map<U: ?Sized>(self, f: fn(&T) -> &U) -> MappedWeakRef<'t, T, U> { MappedWeakRef { r: self, f } }
+
+    /// Erases the element type, see [`DynWeakRef`]
+    #[must_use]
+    pub fn into_dyn<U: ?Sized>(self) -> DynWeakRef<'t, U>
+    where
+        T: AsDyn<U>,
+    {
+        DynWeakRef {
+            r:       DynSlot::new(self.slot, self.pool),
+            version: self.version,
+        }
+    }
 }
 
 impl<'t, T> WeakRefTrait for WeakRef<'t, T> {
@@ -147,14 +514,14 @@ impl<'t, T> WeakRefTrait for WeakRef<'t, T> {
     #[must_use]
     fn upgrade(&self) -> Option<StrongRef<'t, T>> {
         if self.is_valid() {
-            Some(StrongRef::new(self.slot))
+            Some(StrongRef::new(self.slot, self.pool))
         } else {
             None
         }
     }
 
     #[must_use]
-    fn is_valid(&self) -> bool { self.version == self.slot.version.get() }
+    fn is_valid(&self) -> bool { self.slot.is_valid(self.version) }
 }
 
 impl<'t, T> From<StrongRef<'t, T>> for WeakRef<'t, T> {
@@ -192,132 +559,2209 @@ impl<'t, T> Debug for WeakRef<'t, T> {
     }
 }
 
-pub struct RcPool<T, A> {
-    slots:      A,
-    first_free: Cell<i32>,
-    len:        Cell<i32>,
-    version:    Cell<i32>,
-    last:       Cell<i32>,
-    _phantom:   PhantomData<fn(T) -> T>,
+/// Conversion of an element to an unsized type, usually a trait object it implements, so that references to elements
+/// of different types, possibly in different pools, can be stored together as [`DynStrongRef`] or [`DynWeakRef`].
+/// The methods are normally just `self`, which the compiler coerces, and one generic impl covers all implementors of
+/// a trait: `impl<T: Component + 'static> AsDyn<dyn Component> for T`.
+pub trait AsDyn<U: ?Sized> {
+    fn as_dyn(&self) -> &U;
+    fn as_dyn_mut(&mut self) -> &mut U;
 }
 
-pub type VecRcPool<T> = RcPool<T, Vec<Slot<T>>>;
-pub type ArrayRcPool<T, const CAP: usize> = RcPool<T, [Slot<T>; CAP]>;
+impl<T> AsDyn<T> for T {
+    fn as_dyn(&self) -> &T { self }
+    fn as_dyn_mut(&mut self) -> &mut T { self }
+}
 
-impl<T> RcPool<T, Vec<Slot<T>>> {
-    #[must_use]
-    pub fn new_vec(cap: usize) -> Self {
-        let mut v = Vec::with_capacity(cap);
-        v.resize_with(v.capacity(), Slot::default);
-        Self::new(v)
+/// Returns a pointer to the element of a live slot whose type has been erased. `mutable` must only be true when the
+/// element is exclusively borrowed.
+unsafe fn dyn_elem<T: AsDyn<U>, U: ?Sized>(header: NonNull<SlotHeader>, mutable: bool) -> *mut U {
+    let slot = header.cast::<Slot<T>>().as_ref();
+
+    if mutable {
+        slot.get_mut().as_dyn_mut()
+    } else {
+        slot.get().as_dyn() as *const U as *mut U
     }
 }
 
-impl<T, A: AsRef<[Slot<T>]>> RcPool<T, A> {
-    #[must_use]
-    pub fn new(slots: A) -> Self {
-        for (i, slot) in slots.as_ref().iter().enumerate() {
-            slot.version.set(-(i as i32 + 2));
-        }
+/// Drops a strong reference to a slot whose type has been erased
+unsafe fn dyn_release<T>(header: NonNull<SlotHeader>, pool: &PoolState) {
+    drop(StrongRef {
+        slot: header.cast::<Slot<T>>().as_ref(),
+        pool,
+    });
+}
+
+/// Slot with its element type erased, the functions are instantiated for the actual element type
+struct DynSlot<'t, U: ?Sized> {
+    slot:    NonNull<SlotHeader>,
+    pool:    &'t PoolState,
+    elem:    unsafe fn(NonNull<SlotHeader>, bool) -> *mut U,
+    release: unsafe fn(NonNull<SlotHeader>, &PoolState),
+    _slot:   PhantomData<&'t SlotHeader>,
+}
 
+impl<'t, U: ?Sized> DynSlot<'t, U> {
+    #[must_use]
+    fn new<T: AsDyn<U>>(slot: &'t Slot<T>, pool: &'t PoolState) -> Self {
         Self {
-            slots,
-            first_free: Cell::new(0),
-            len: Cell::new(0),
-            version: Cell::new(0),
-            last: Cell::new(0),
-            _phantom: Default::default(),
+            // Points to the whole slot, not just the header, so that the element can be reached through it
+            slot: NonNull::from(slot).cast(),
+            pool,
+            elem: dyn_elem::<T, U>,
+            release: dyn_release::<T>,
+            _slot: PhantomData,
         }
     }
 
     #[must_use]
-    pub fn get(&self, index: usize) -> Option<StrongRef<T>> {
-        self.slots.as_ref().get(index).and_then(
-            |slot| {
-                if slot.version.get() >= 0 {
-                    Some(StrongRef { slot })
-                } else {
-                    None
-                }
-            },
-        )
-    }
+    fn header(&self) -> &'t SlotHeader { unsafe { self.slot.cast().as_ref() } }
+}
 
-    pub fn iter(&self) -> impl Iterator<Item = StrongRef<T>> {
-        self.slots.as_ref().iter().take(self.last.get() as usize).filter_map(|slot| {
-            if slot.version.get() >= 0 {
-                Some(StrongRef::new(slot))
-            } else {
-                None
-            }
-        })
+impl<'t, U: ?Sized> Clone for DynSlot<'t, U> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<'t, U: ?Sized> Copy for DynSlot<'t, U> {}
+
+/// Strong reference to a pool element with its type erased, usually to a trait object, created with
+/// [`StrongRef::into_dyn`]. It behaves like the [`StrongRef`] it was created from.
+pub struct DynStrongRef<'t, U: ?Sized> {
+    r: DynSlot<'t, U>,
+}
+
+impl<'t, U: ?Sized> DynStrongRef<'t, U> {
+    #[must_use]
+    pub fn weak(&self) -> DynWeakRef<'t, U> {
+        DynWeakRef {
+            r:       self.r,
+            version: self.r.header().version.get(),
+        }
     }
 
     #[must_use]
-    pub fn len(&self) -> usize { self.len.get() as usize }
+    pub fn key<I: Index, G: Index>(&self) -> PoolKey<I, G> { self.r.header().key() }
 
     #[must_use]
-    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    pub fn is_unique(&self) -> bool { self.r.header().ref_count.get() == 1 }
 
+    /// Index of the element's slot in its pool
     #[must_use]
-    pub fn capacity(&self) -> usize { self.slots.as_ref().len() }
+    pub fn index(&self) -> usize { self.r.header().index.get() as usize }
 
+    /// Id of the pool the element belongs to
     #[must_use]
-    fn index_of(&self, slot: &Slot<T>) -> usize {
-        let index = unsafe { (slot as *const Slot<T>).offset_from(self.slots.as_ref().as_ptr()) };
+    pub fn pool_id(&self) -> PoolId { self.r.pool.id }
 
-        if index < 0 || index as usize >= self.capacity() {
-            panic!();
-        }
+    pub fn borrow<'u>(&'u self) -> DynRef<'u, 't, U> { self.try_borrow().expect("Element is mutably borrowed!") }
+
+    pub fn try_borrow<'u>(&'u self) -> Option<DynRef<'u, 't, U>> {
+        self.r.header().try_borrow().then(|| DynRef { r: self })
+    }
+
+    pub fn borrow_mut<'u>(&'u self) -> DynRefMut<'u, 't, U> {
+        self.try_borrow_mut().expect("Element is already borrowed!")
+    }
+
+    pub fn try_borrow_mut<'u>(&'u self) -> Option<DynRefMut<'u, 't, U>> {
+        self.r.header().try_borrow_mut().then(|| DynRefMut { r: self })
+    }
+}
+
+impl<'t, U: ?Sized> Clone for DynStrongRef<'t, U> {
+    fn clone(&self) -> Self {
+        self.r.header().acquire(self.r.pool);
+        Self { r: self.r }
+    }
+}
+
+impl<'t, U: ?Sized> Drop for DynStrongRef<'t, U> {
+    fn drop(&mut self) { unsafe { (self.r.release)(self.r.slot, self.r.pool) } }
+}
+
+impl<'t, U: ?Sized + Debug> Debug for DynStrongRef<'t, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("DynStrongRef");
+        d.field("index", &self.index()).field("version", &self.r.header().version.get());
+
+        match self.try_borrow() {
+            Some(r) => d.field("value", &&*r),
+            None => d.field("value", &format_args!("<borrowed>")),
+        };
 
-        index as usize
+        d.finish()
     }
+}
+
+impl<'t, U: ?Sized> crate::refs::StrongRefTrait for DynStrongRef<'t, U> {
+    type Target = U;
+    type Weak = DynWeakRef<'t, U>;
+    fn downgrade(&self) -> DynWeakRef<'t, U> { self.weak() }
+}
+
+/// Shared borrow of a pool element through a [`DynStrongRef`]
+pub struct DynRef<'t, 'u, U: ?Sized> {
+    r: &'t DynStrongRef<'u, U>,
+}
+
+impl<'t, 'u, U: ?Sized> Deref for DynRef<'t, 'u, U> {
+    type Target = U;
+    fn deref(&self) -> &U { unsafe { &*(self.r.r.elem)(self.r.r.slot, false) } }
+}
+
+impl<'t, 'u, U: ?Sized> Drop for DynRef<'t, 'u, U> {
+    fn drop(&mut self) { self.r.r.header().release_borrow() }
+}
+
+/// Exclusive borrow of a pool element through a [`DynStrongRef`]
+pub struct DynRefMut<'t, 'u, U: ?Sized> {
+    r: &'t DynStrongRef<'u, U>,
+}
+
+impl<'t, 'u, U: ?Sized> Deref for DynRefMut<'t, 'u, U> {
+    type Target = U;
+    fn deref(&self) -> &U { unsafe { &*(self.r.r.elem)(self.r.r.slot, false) } }
+}
+
+impl<'t, 'u, U: ?Sized> DerefMut for DynRefMut<'t, 'u, U> {
+    fn deref_mut(&mut self) -> &mut U { unsafe { &mut *(self.r.r.elem)(self.r.r.slot, true) } }
+}
+
+impl<'t, 'u, U: ?Sized> Drop for DynRefMut<'t, 'u, U> {
+    fn drop(&mut self) { self.r.r.header().release_borrow_mut() }
+}
+
+/// Weak reference to a pool element with its type erased, created with [`WeakRef::into_dyn`]. References into pools
+/// with different element types can be stored together this way.
+pub struct DynWeakRef<'t, U: ?Sized> {
+    r:       DynSlot<'t, U>,
+    version: i32,
+}
+
+impl<'t, U: ?Sized> DynWeakRef<'t, U> {
+    #[must_use]
+    pub fn get(&self) -> Option<DynStrongRef<'t, U>> { self.upgrade() }
+
+    #[must_use]
+    pub fn key<I: Index, G: Index>(&self) -> PoolKey<I, G> { PoolKey::new(self.r.header().index.get(), self.version) }
+
+    /// Index of the element's slot in its pool
+    #[must_use]
+    pub fn index(&self) -> usize { self.r.header().index.get() as usize }
 
+    /// Id of the pool the element belongs to
     #[must_use]
-    pub fn insert(&self, value: T) -> Option<StrongRef<T>> {
-        let index = self.first_free.get();
-        let v = self.version.get();
+    pub fn pool_id(&self) -> PoolId { self.r.pool.id }
+}
+
+impl<'t, U: ?Sized> WeakRefTrait for DynWeakRef<'t, U> {
+    type Target = U;
+    type Strong = DynStrongRef<'t, U>;
 
-        if let Some(slot) = self.slots.as_ref().get(index as usize) {
-            self.first_free.set(-slot.version.get() - 1);
-            self.version.set(v.wrapping_add(1) & i32::MAX);
-            slot.version.set(v);
-            self.last.set(self.last.get().max(index + 1));
-            unsafe { (*slot.elem.get()).write(value) };
-            slot.ref_count.set(1);
-            Some(StrongRef { slot })
+    fn upgrade(&self) -> Option<DynStrongRef<'t, U>> {
+        if self.is_valid() {
+            self.r.header().acquire(self.r.pool);
+            Some(DynStrongRef { r: self.r })
         } else {
             None
         }
     }
 
-    pub fn remove(&self, r: &WeakRef<T>) {
-        if r.is_valid() {
-            let index = self.index_of(r.slot);
-            let ff = self.first_free.get();
-            self.first_free.set(index as i32);
-            self.last.set(self.last.get().min(index as i32 + 1));
-            r.slot.version.set(-ff - 1);
-            r.slot.drop_elem();
-        }
-    }
+    fn is_valid(&self) -> bool { self.r.header().is_valid(self.version) }
 }
 
-impl<T: Clear, A: AsRef<[Slot<T>]>> Clear for RcPool<T, A> {
-    fn clear(&self) {
-        let last = self.last.get();
-        self.first_free.set(0);
-        self.len.set(0);
-        self.last.set(0);
+impl<'t, U: ?Sized> Clone for DynWeakRef<'t, U> {
+    fn clone(&self) -> Self { *self }
+}
 
-        for i in 0..last {
-            let slot = unsafe { self.slots.as_ref().get_unchecked(i as usize) };
-            slot.version.set(-(i + 2));
-            slot.drop_elem();
-        }
+impl<'t, U: ?Sized> Copy for DynWeakRef<'t, U> {}
+
+impl<'t, U: ?Sized> PartialEq for DynWeakRef<'t, U> {
+    fn eq(&self, other: &Self) -> bool { self.r.slot == other.r.slot && self.version == other.version }
+}
+
+impl<'t, U: ?Sized> Eq for DynWeakRef<'t, U> {}
+
+impl<'t, U: ?Sized> std::hash::Hash for DynWeakRef<'t, U> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.r.slot.hash(state);
+        self.version.hash(state);
     }
 }
 
-impl<T, A: AsRef<[Slot<T>]> + Default> Default for RcPool<T, A> {
-    fn default() -> Self { Self::new(A::default()) }
+impl<'t, U: ?Sized> Debug for DynWeakRef<'t, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynWeakRef").field("slot", &self.r.slot).field("version", &self.version).finish()
+    }
+}
+
+/// Lifetime free handle to a pool element, can be resolved back to a reference through the pool it was created from.
+/// The index type `I` and generation type `G` decide the size of the key, only the low bits of the element version
+/// that fit in `G` are stored and compared.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PoolKey<I = u32, G = u32> {
+    index:   I,
+    version: G,
+}
+
+impl<I: Index, G: Index> PoolKey<I, G> {
+    #[must_use]
+    pub(crate) fn new(index: i32, version: i32) -> Self {
+        debug_assert!(index as usize <= I::MAX, "Slot index doesn't fit in the key index type!");
+
+        Self {
+            index:   I::from_usize(index as usize),
+            version: G::from_usize(version as usize),
+        }
+    }
+
+    #[must_use]
+    pub fn index(&self) -> usize { self.index.to_usize() }
+
+    #[must_use]
+    pub fn version(&self) -> G { self.version }
+
+    #[must_use]
+    fn matches<T>(&self, slot: &Slot<T>) -> bool {
+        let v = slot.version.get();
+        v >= 0 && G::from_usize(v as usize).to_usize() == self.version.to_usize()
+    }
+}
+
+impl<'t, T, I: Index, G: Index> From<WeakRef<'t, T>> for PoolKey<I, G> {
+    fn from(r: WeakRef<'t, T>) -> Self { r.key() }
+}
+
+impl<'t, T, I: Index, G: Index> From<&StrongRef<'t, T>> for PoolKey<I, G> {
+    fn from(r: &StrongRef<'t, T>) -> Self { r.key() }
+}
+
+/// Unique id of an [`RcPool`] or [`SyncRcPool`](crate::sync_rc_pool::SyncRcPool), used to detect references that are
+/// passed to a different pool than their own
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PoolId(u64);
+
+impl PoolId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl std::fmt::Display for PoolId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "#{}", self.0) }
+}
+
+/// Type erased [`ClearMut::clear_mut`], so that dropping a reference can recycle an element without a `T: ClearMut`
+/// bound
+unsafe fn clear_elem<T: ClearMut>(elem: *mut ()) { (*elem.cast::<T>()).clear_mut() }
+
+/// Free list and counters of an [`RcPool`], shared with its references so that the last strong reference to a
+/// removed element can give the slot back to the pool
+struct PoolState {
+    id:             PoolId,
+    first_free:     Cell<i32>,
+    len:            Cell<i32>,
+    last:           Cell<i32>,
+    clock:          Cell<u32>,
+    retired:        Cell<u32>,
+    max_generation: i32,
+    auto_remove:    bool,
+    recycle:        Option<unsafe fn(*mut ())>,
+    events:         Option<RefCell<Vec<PoolEvent>>>,
+    ordered:        bool,
+    head:           Cell<i32>,
+    tail:           Cell<i32>,
+}
+
+impl PoolState {
+    /// Advances the clock used to find the least recently used element
+    fn tick(&self) -> u32 {
+        let c = self.clock.get().wrapping_add(1);
+        self.clock.set(c);
+        c
+    }
+
+    fn emit(&self, event: PoolEvent) {
+        if let Some(events) = &self.events {
+            events.borrow_mut().push(event);
+        }
+    }
+
+    /// Records the event for a slot that is about to be freed because its last strong reference is gone
+    fn emit_release<T>(&self, slot: &Slot<T>) {
+        if slot.version.get() == REMOVED_VERSION {
+            self.emit(PoolEvent::Drop(slot.key()));
+        } else {
+            self.emit(PoolEvent::Remove(slot.key()));
+        }
+    }
+
+    /// Pushes a slot on the free list, the element must be dropped separately. `last` is left as it is since
+    /// slots after this one might still be in use. A slot whose generation can't be increased anymore is retired
+    /// instead.
+    fn free<T>(&self, slot: &Slot<T>) {
+        if slot.version.get() >= 0 {
+            self.len.sub(1);
+        }
+
+        if slot.generation.get() >= self.max_generation {
+            slot.version.set(RETIRED_VERSION);
+            self.retired.add(1);
+            return;
+        }
+
+        slot.generation.add(1);
+        let ff = self.first_free.get();
+        self.first_free.set(slot.index.get());
+        slot.version.set(-ff - 1);
+    }
+}
+
+struct FreeGuard<'t, T> {
+    slot: &'t Slot<T>,
+    pool: &'t PoolState,
+}
+
+impl<'t, T> Drop for FreeGuard<'t, T> {
+    fn drop(&mut self) { self.pool.free(self.slot) }
+}
+
+/// Statistics returned by [`RcPool::stats`]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PoolStats {
+    /// Number of elements in the pool
+    pub len:      usize,
+    pub capacity: usize,
+
+    /// Number of slots in the free list
+    pub free: usize,
+
+    /// Number of removed elements that are kept alive by strong references
+    pub removed: usize,
+
+    /// One past the highest slot index that has been used
+    pub last: usize,
+
+    /// Number of elements, including removed ones, that have strong references
+    pub referenced: usize,
+
+    /// Number of elements, including removed ones, for each strong reference count
+    pub ref_counts: BTreeMap<u32, usize>,
+
+    /// Number of slots that were retired because their generation would wrap around
+    pub retired: usize,
+}
+
+/// Change to the elements of a pool, recorded if the pool was created with [`RcPool::with_events`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PoolEvent<I = u32, G = u32> {
+    Insert(PoolKey<I, G>),
+
+    /// The element was removed, if it had strong references it stays alive until a [`PoolEvent::Drop`] for it
+    Remove(PoolKey<I, G>),
+
+    /// The last strong reference to a removed element was dropped or turned into the element with
+    /// [`StrongRef::into_inner`]
+    Drop(PoolKey<I, G>),
+
+    /// The element was moved by [`RcPool::compact`]
+    Move {
+        from: PoolKey<I, G>,
+        to:   PoolKey<I, G>,
+    },
+}
+
+impl PoolEvent {
+    #[must_use]
+    fn cast<I: Index, G: Index>(self) -> PoolEvent<I, G> {
+        let cast = |key: PoolKey| PoolKey::new(key.index() as i32, key.version() as i32);
+
+        match self {
+            Self::Insert(key) => PoolEvent::Insert(cast(key)),
+            Self::Remove(key) => PoolEvent::Remove(cast(key)),
+            Self::Drop(key) => PoolEvent::Drop(cast(key)),
+            Self::Move { from, to } => PoolEvent::Move {
+                from: cast(from),
+                to:   cast(to),
+            },
+        }
+    }
+}
+
+/// What happens when an element is inserted into a full pool
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverflowPolicy {
+    /// The insertion fails
+    Reject,
+
+    /// The slot storage grows if it supports it, otherwise the insertion fails. Retired slots are never reused, so with
+    /// constant insertions and removals the storage keeps growing until the index type's limit.
+    #[default]
+    Grow,
+
+    /// The least recently used element without strong references is removed to make room, if all elements are
+    /// referenced the insertion fails. An element is used when a strong reference to it is created.
+    ///
+    /// The time of the last use is stored in every slot, 4 bytes per slot, whatever the policy.
+    EvictLru,
+}
+
+/// Error returned by [`RcPool::try_insert`] when the pool is full, contains the element that couldn't be inserted
+pub struct InsertError<T> {
+    pub(crate) value: T,
+}
+
+impl<T> InsertError<T> {
+    #[must_use]
+    pub fn into_inner(self) -> T { self.value }
+}
+
+impl<T> Debug for InsertError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.debug_struct("InsertError").finish() }
+}
+
+impl<T> std::fmt::Display for InsertError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("Pool is full!") }
+}
+
+impl<T> std::error::Error for InsertError<T> {}
+
+/// Implemented by elements that hold references into their own pool so that [`RcPool::clone_into`] can clone them
+/// into another pool. [`PoolKey`]s stay valid in the clone as they are and don't need to be remapped.
+pub trait RemapRefs<'u> {
+    /// Type of the cloned element, usually `Self` with the lifetime of the new pool
+    type Output;
+
+    fn remap_refs(&self, map: &RefMap<'u, Self::Output>) -> Self::Output;
+}
+
+/// Translates references into a pool that is being cloned with [`RcPool::clone_into`] to references into the clone
+pub struct RefMap<'u, U> {
+    source: PoolId,
+    pool:   &'u PoolState,
+    slots:  &'u dyn SlotStorage<U>,
+}
+
+impl<'u, U> RefMap<'u, U> {
+    /// Returns a reference to the clone of the element `r` refers to, it's valid in the new pool if `r` is valid in
+    /// the original one
+    ///
+    /// # Panics
+    ///
+    /// If `r` belongs to another pool than the one being cloned.
+    #[must_use]
+    pub fn weak<T>(&self, r: &WeakRef<'_, T>) -> WeakRef<'u, U> {
+        if r.pool.id != self.source {
+            panic!("Reference to an element of pool {} used to clone pool {}!", r.pool.id, self.source)
+        }
+
+        // The new pool has at least as many slots as the original has used
+        let slot = unsafe { self.slots.slot(r.index()).unwrap_unchecked() };
+
+        WeakRef {
+            slot,
+            pool: self.pool,
+            version: r.version,
+        }
+    }
+}
+
+/// Pool of reference counted elements. The index type `I` limits the capacity of the pool and together with the
+/// generation type `G` decides the size of the pool's [`PoolKey`]s.
+///
+/// A slot is retired for good once its generation reaches the largest value `G` can hold, capped at `i32::MAX`, so
+/// that keys to earlier elements in it can't become valid again. Each slot can therefore hold at most that many
+/// elements plus one over the lifetime of the pool, and once all slots are retired no element can be inserted anymore.
+pub struct RcPool<T, A, I = u32, G = u32> {
+    slots:    A,
+    state:    PoolState,
+    overflow: OverflowPolicy,
+    _phantom: PhantomData<fn(T) -> T>,
+    _key:     PhantomData<PoolKey<I, G>>,
+}
+
+pub type VecRcPool<T> = RcPool<T, Vec<Slot<T>>>;
+pub type ArrayRcPool<T, const CAP: usize> = RcPool<T, [Slot<T>; CAP]>;
+pub type ChunkedRcPool<T, const CHUNK: usize = 256> = RcPool<T, ChunkedSlots<T, CHUNK>>;
+
+/// Growable pool limited to 65536 elements that hands out 4 byte keys. Every slot is retired after 65536
+/// insertions, so the pool is full for good after about 2^32 insertions in total, see [`RcPool`].
+pub type CompactRcPool<T, const CHUNK: usize = 256> = RcPool<T, ChunkedSlots<T, CHUNK>, u16, u16>;
+
+impl<T, I: Index, G: Index> RcPool<T, Vec<Slot<T>>, I, G> {
+    #[must_use]
+    pub fn new_vec(cap: usize) -> Self {
+        let mut v = Vec::with_capacity(cap);
+        v.resize_with(v.capacity(), Slot::default);
+        Self::new(v)
+    }
+}
+
+impl<T, const CHUNK: usize, I: Index, G: Index> RcPool<T, ChunkedSlots<T, CHUNK>, I, G> {
+    /// Creates a pool that allocates a new chunk of `CHUNK` slots whenever it runs out of free slots
+    #[must_use]
+    pub fn new_chunked(cap: usize) -> Self { Self::new(ChunkedSlots::with_capacity(cap)) }
+}
+
+impl<T, A: SlotStorage<T>, I: Index, G: Index> RcPool<T, A, I, G> {
+    /// Maximum number of slots the pool can use, limited by the index type
+    pub const MAX_CAPACITY: usize = if I::MAX < i32::MAX as usize - 2 { I::MAX + 1 } else { i32::MAX as usize - 2 };
+
+    #[must_use]
+    pub fn new(slots: A) -> Self {
+        let s = Self {
+            slots,
+            state: PoolState {
+                id:             PoolId::next(),
+                first_free:     Cell::new(0),
+                len:            Cell::new(0),
+                last:           Cell::new(0),
+                clock:          Cell::new(0),
+                retired:        Cell::new(0),
+                max_generation: G::MAX.min(i32::MAX as usize) as i32,
+                auto_remove:    false,
+                recycle:        None,
+                events:         None,
+                ordered:        false,
+                head:           Cell::new(NO_SLOT),
+                tail:           Cell::new(NO_SLOT),
+            },
+            overflow: Default::default(),
+            _phantom: Default::default(),
+            _key: Default::default(),
+        };
+
+        s.init_slots(0);
+        s
+    }
+
+    /// Makes the pool behave like `Rc`, an element is removed as soon as its last strong reference is dropped
+    /// instead of staying alive until [`RcPool::remove`] is called.
+    ///
+    /// # Panics
+    ///
+    /// If the pool isn't empty, its elements would have no strong references.
+    #[must_use]
+    pub fn with_auto_remove(mut self) -> Self {
+        assert!(self.is_empty(), "Pool has elements without strong references!");
+        self.state.auto_remove = true;
+        self
+    }
+
+    /// Sets what happens when an element is inserted into a full pool, the default is [`OverflowPolicy::Grow`]
+    #[must_use]
+    pub fn with_overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// Records a [`PoolEvent`] for every change to the elements, which can be collected with
+    /// [`RcPool::take_events`]
+    #[must_use]
+    pub fn with_events(mut self) -> Self {
+        self.state.events.get_or_insert_with(Default::default);
+        self
+    }
+
+    /// Returns the events recorded since the last call, oldest first. Always empty if the pool wasn't created with
+    /// [`RcPool::with_events`].
+    #[must_use]
+    pub fn take_events(&self) -> Vec<PoolEvent<I, G>> {
+        let events = self.state.events.as_ref().map(|e| e.take()).unwrap_or_default();
+        events.into_iter().map(PoolEvent::cast).collect()
+    }
+
+    /// Makes removed elements be cleared with [`ClearMut`] and kept in their slot instead of being dropped, so that
+    /// [`RcPool::insert_with`] can reuse their allocations
+    #[must_use]
+    pub fn with_recycling(mut self) -> Self
+    where
+        T: ClearMut,
+    {
+        self.state.recycle = Some(clear_elem::<T>);
+        self
+    }
+
+    /// Keeps the elements in a list in insertion order, which [`RcPool::iter`] follows instead of the slot order.
+    /// Elements inserted before this call are added to the list in slot order.
+    ///
+    /// The links are stored in every slot, 8 bytes per slot, whether or not a pool uses this.
+    #[must_use]
+    pub fn with_insertion_order(mut self) -> Self {
+        if !self.state.ordered {
+            self.state.ordered = true;
+            self.live_slots().for_each(|slot| self.link(slot, self.state.tail.get(), NO_SLOT));
+        }
+
+        self
+    }
+
+    /// Links all slots from `start` to the end of the storage into the free list
+    fn init_slots(&self, start: usize) {
+        for i in start..self.capacity() {
+            let slot = unsafe { self.slot_unchecked(i) };
+            slot.index.set(i as i32);
+            slot.version.set(-(i as i32 + 2));
+            slot.prev.set(NO_SLOT);
+            slot.next.set(NO_SLOT);
+        }
+    }
+
+    #[must_use]
+    unsafe fn slot_unchecked(&self, index: usize) -> &Slot<T> { self.slots.slot(index).unwrap_unchecked() }
+
+    /// Returns the slot at `index` if it contains an element
+    #[must_use]
+    fn live_slot(&self, index: usize) -> Option<&Slot<T>> {
+        if index < self.capacity() {
+            self.slots.slot(index).filter(|slot| slot.version.get() >= 0)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a strong reference to the element in the slot at `index`
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<StrongRef<'_, T>> {
+        self.live_slot(index).map(|slot| StrongRef::new(slot, &self.state))
+    }
+
+    /// Returns a weak reference to the element in the slot at `index`
+    #[must_use]
+    pub fn get_weak(&self, index: usize) -> Option<WeakRef<'_, T>> {
+        self.live_slot(index).map(|slot| WeakRef::new(slot, &self.state))
+    }
+
+    /// Returns true if the slot at `index` contains an element
+    #[must_use]
+    pub fn contains_index(&self, index: usize) -> bool { self.live_slot(index).is_some() }
+
+    /// Returns the slot index of the referenced element, or `None` if the reference is to another pool
+    #[must_use]
+    pub fn index_of(&self, r: &StrongRef<T>) -> Option<usize> {
+        if self.owns(r.pool) {
+            Some(r.index())
+        } else {
+            None
+        }
+    }
+
+    /// Returns a weak reference to the element the key was created from, or `None` if it has been removed
+    #[must_use]
+    pub fn get_by_key(&self, key: PoolKey<I, G>) -> Option<WeakRef<'_, T>> {
+        self.key_slot(key).map(|slot| WeakRef::new(slot, &self.state))
+    }
+
+    /// Returns the slot of the element the key was created from if it hasn't been removed
+    #[must_use]
+    fn key_slot(&self, key: PoolKey<I, G>) -> Option<&Slot<T>> {
+        if key.index() < self.capacity() {
+            self.slots.slot(key.index()).filter(|slot| key.matches(slot))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the element the key was created from, or `None` if it has been removed. Exclusive access to the pool
+    /// means there can't be any references to its elements, so no borrow checks are needed.
+    #[must_use]
+    pub fn get_mut(&mut self, key: PoolKey<I, G>) -> Option<&mut T> {
+        self.key_slot(key).map(|slot| unsafe { slot.get_mut() })
+    }
+
+    /// Returns the elements for several keys at once, or `None` if any of them has been removed or two keys refer
+    /// to the same element
+    #[must_use]
+    pub fn get_disjoint_mut<const N: usize>(&mut self, keys: [PoolKey<I, G>; N]) -> Option<[&mut T; N]> {
+        let slots = keys.map(|key| self.key_slot(key));
+
+        for (i, slot) in slots.iter().enumerate() {
+            let slot = (*slot)?;
+
+            if slots[..i].iter().any(|s| s.is_some_and(|s| std::ptr::eq(s, slot))) {
+                return None;
+            }
+        }
+
+        Some(slots.map(|slot| unsafe { slot.unwrap_unchecked().get_mut() }))
+    }
+
+    /// Iterates over mutable references to the elements, in the same order as [`RcPool::iter`]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        let linked =
+            std::iter::successors(self.next_linked(self.state.head.get()), |slot| self.next_linked(slot.next.get()));
+
+        let slots = (!self.state.ordered).then(|| self.live_slots());
+        linked.chain(slots.into_iter().flatten()).map(|slot| unsafe { slot.get_mut() })
+    }
+
+    /// Returns a strong reference to the element the key was created from, or `None` if it has been removed
+    #[must_use]
+    pub fn upgrade_key(&self, key: PoolKey<I, G>) -> Option<StrongRef<'_, T>> {
+        self.get_by_key(key).and_then(|r| r.get())
+    }
+
+    /// Iterates over the elements in slot order, or in insertion order if the pool was created with
+    /// [`RcPool::with_insertion_order`]. In the latter case the iterator holds a strong reference to the next element
+    /// so that elements can be removed while iterating.
+    pub fn iter(&self) -> Iter<'_, T, A, I, G> {
+        Iter {
+            pool:  self,
+            index: 0,
+            end:   self.state.last.get() as usize,
+            next:  self.next_linked(self.state.head.get()).map(|slot| StrongRef::acquire(slot, &self.state)),
+        }
+    }
+
+    /// Returns the first element in insertion order, or `None` if the pool is empty or doesn't keep insertion order
+    #[must_use]
+    pub fn front(&self) -> Option<StrongRef<'_, T>> {
+        self.next_linked(self.state.head.get()).map(|slot| StrongRef::new(slot, &self.state))
+    }
+
+    /// Returns the last element in insertion order, or `None` if the pool is empty or doesn't keep insertion order
+    #[must_use]
+    pub fn back(&self) -> Option<StrongRef<'_, T>> {
+        let mut index = self.state.tail.get();
+
+        while index != NO_SLOT {
+            let slot = unsafe { self.slot_unchecked(index as usize) };
+
+            if slot.version.get() >= 0 {
+                return Some(StrongRef::new(slot, &self.state));
+            }
+
+            index = slot.prev.get();
+        }
+
+        None
+    }
+
+    /// Moves the element to the front of the insertion order, returns false if it has been removed
+    ///
+    /// # Panics
+    ///
+    /// If the pool doesn't keep insertion order or `r` belongs to another pool.
+    pub fn move_to_front(&self, r: &WeakRef<T>) -> bool { self.move_linked(r, true) }
+
+    /// Moves the element to the back of the insertion order, returns false if it has been removed
+    ///
+    /// # Panics
+    ///
+    /// If the pool doesn't keep insertion order or `r` belongs to another pool.
+    pub fn move_to_back(&self, r: &WeakRef<T>) -> bool { self.move_linked(r, false) }
+
+    fn move_linked(&self, r: &WeakRef<T>, front: bool) -> bool {
+        assert!(self.state.ordered, "Pool doesn't keep insertion order!");
+        self.check_owner(r.pool);
+
+        if !r.is_valid() {
+            return false;
+        }
+
+        self.unlink(r.slot);
+
+        if front {
+            self.link(r.slot, NO_SLOT, self.state.head.get());
+        } else {
+            self.link(r.slot, self.state.tail.get(), NO_SLOT);
+        }
+
+        true
+    }
+
+    /// Returns true if the slot is in the insertion order list. Live slots of an ordered pool always are, other slots
+    /// are removed from the list when they're freed by the pool or reused.
+    #[must_use]
+    fn is_linked(&self, slot: &Slot<T>) -> bool {
+        slot.prev.get() != NO_SLOT || self.state.head.get() == slot.index.get()
+    }
+
+    /// Inserts the slot between the adjacent list entries `prev` and `next`
+    fn link(&self, slot: &Slot<T>, prev: i32, next: i32) {
+        let index = slot.index.get();
+        slot.prev.set(prev);
+        slot.next.set(next);
+
+        match prev {
+            NO_SLOT => self.state.head.set(index),
+            _ => unsafe { self.slot_unchecked(prev as usize) }.next.set(index),
+        }
+
+        match next {
+            NO_SLOT => self.state.tail.set(index),
+            _ => unsafe { self.slot_unchecked(next as usize) }.prev.set(index),
+        }
+    }
+
+    /// Removes the slot from the insertion order list if it's in it
+    fn unlink(&self, slot: &Slot<T>) {
+        if !self.is_linked(slot) {
+            return;
+        }
+
+        let (prev, next) = (slot.prev.replace(NO_SLOT), slot.next.replace(NO_SLOT));
+
+        match prev {
+            NO_SLOT => self.state.head.set(next),
+            _ => unsafe { self.slot_unchecked(prev as usize) }.next.set(next),
+        }
+
+        match next {
+            NO_SLOT => self.state.tail.set(prev),
+            _ => unsafe { self.slot_unchecked(next as usize) }.prev.set(prev),
+        }
+    }
+
+    /// Returns the first live slot in the insertion order list starting at `index`
+    #[must_use]
+    fn next_linked(&self, mut index: i32) -> Option<&Slot<T>> {
+        while index != NO_SLOT {
+            let slot = unsafe { self.slot_unchecked(index as usize) };
+
+            if slot.version.get() >= 0 {
+                return Some(slot);
+            }
+
+            index = slot.next.get();
+        }
+
+        None
+    }
+
+    fn live_slots(&self) -> impl Iterator<Item = &Slot<T>> {
+        (0..self.state.last.get() as usize)
+            .map(|i| unsafe { self.slot_unchecked(i) })
+            .filter(|slot| slot.version.get() >= 0)
+    }
+
+    /// Collects statistics about the pool, this walks through all used slots
+    #[must_use]
+    pub fn stats(&self) -> PoolStats {
+        let mut stats = PoolStats {
+            len: self.len(),
+            capacity: self.capacity(),
+            last: self.state.last.get() as usize,
+            retired: self.state.retired.get() as usize,
+            ..Default::default()
+        };
+
+        for i in 0..stats.last {
+            let slot = unsafe { self.slot_unchecked(i) };
+            let version = slot.version.get();
+
+            if version == REMOVED_VERSION {
+                stats.removed += 1;
+            } else if version < 0 {
+                continue;
+            }
+
+            if slot.ref_count.get() > 0 {
+                stats.referenced += 1;
+            }
+
+            *stats.ref_counts.entry(slot.ref_count.get()).or_default() += 1;
+        }
+
+        stats.free = stats.capacity - stats.len - stats.removed - stats.retired;
+        stats
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize { self.state.len.get() as usize }
+
+    #[must_use]
+    pub fn id(&self) -> PoolId { self.state.id }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    #[must_use]
+    pub fn capacity(&self) -> usize { self.slots.capacity().min(Self::MAX_CAPACITY) }
+
+    #[must_use]
+    fn owns(&self, pool: &PoolState) -> bool { pool.id == self.state.id }
+
+    /// Panics if a reference from another pool is used with this one
+    fn check_owner(&self, pool: &PoolState) {
+        if !self.owns(pool) {
+            panic!("Reference to an element of pool {} used with pool {}!", pool.id, self.state.id)
+        }
+    }
+
+    /// Tries to add more slots to the storage, returns false if the storage has a fixed capacity
+    fn grow(&self) -> bool {
+        let cap = self.capacity();
+
+        if cap < Self::MAX_CAPACITY && self.slots.grow() {
+            self.init_slots(cap);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[must_use]
+    pub fn insert(&self, value: T) -> Option<StrongRef<'_, T>> { self.try_insert(value).ok() }
+
+    /// Inserts an element, if the pool is full and no room can be made according to the pool's [`OverflowPolicy`]
+    /// the element is returned in the error
+    pub fn try_insert(&self, value: T) -> Result<StrongRef<'_, T>, InsertError<T>> {
+        match self.make_room() {
+            Some(slot) => Ok(self.insert_at(slot, |_| value)),
+            None => Err(InsertError { value }),
+        }
+    }
+
+    /// Inserts an element initialized by `f`, which gets a recycled element if the pool was created with
+    /// [`RcPool::with_recycling`] and one is available, or a default one otherwise
+    pub fn insert_with(&self, f: impl FnOnce(&mut T)) -> Option<StrongRef<'_, T>>
+    where
+        T: Default,
+    {
+        self.make_room().map(|slot| {
+            let recycled = unsafe { (*slot.elem.get()).take() };
+
+            self.insert_at(slot, |_| {
+                let mut value = recycled.unwrap_or_default();
+                f(&mut value);
+                value
+            })
+        })
+    }
+
+    /// Inserts the element returned by `f`, which gets a weak reference to the element itself. The weak reference
+    /// can't be upgraded until the insertion is done. If the pool is full `f` isn't called.
+    pub fn insert_cyclic<'t>(&'t self, f: impl FnOnce(WeakRef<'t, T>) -> T) -> Option<StrongRef<'t, T>> {
+        self.make_room().map(|slot| self.insert_at(slot, f))
+    }
+
+    /// Clones all elements into `target`, which must be unused, at the same indices and versions as in this pool.
+    /// References between elements are remapped with [`RemapRefs`] and keys stay valid, removed elements that are
+    /// still referenced aren't cloned.
+    ///
+    /// # Panics
+    ///
+    /// If `target` has been used, was created with [`RcPool::with_auto_remove`], since the cloned elements have no
+    /// strong references, or can't get as many slots as this pool has used.
+    pub fn clone_into<'u, B: SlotStorage<T::Output>>(&self, target: &'u RcPool<T::Output, B, I, G>)
+    where
+        T: RemapRefs<'u>,
+    {
+        self.clone_into_with(target, T::remap_refs);
+    }
+
+    /// Same as [`RcPool::clone_into`] but the elements are cloned with `f` instead of [`RemapRefs`]
+    pub fn clone_into_with<'u, U, B: SlotStorage<U>>(
+        &self,
+        target: &'u RcPool<U, B, I, G>,
+        mut f: impl FnMut(&T, &RefMap<'u, U>) -> U,
+    ) {
+        assert_eq!(target.state.last.get(), 0, "Target pool has been used!");
+        assert!(!target.state.auto_remove, "Target pool removes unreferenced elements!");
+        let last = self.state.last.get() as usize;
+
+        while target.capacity() < last {
+            assert!(target.grow(), "Target pool is too small!");
+        }
+
+        let map = RefMap {
+            source: self.state.id,
+            pool:   &target.state,
+            slots:  &target.slots,
+        };
+
+        // Clone everything before touching the target so that `f` can't observe it half done
+        let mut values = Vec::with_capacity(last);
+
+        for i in 0..last {
+            let slot = unsafe { self.slot_unchecked(i) };
+            let (version, generation) = (slot.version.get(), slot.generation.get());
+
+            let value = (version >= 0).then(|| {
+                let r = StrongRef::acquire(slot, &self.state);
+                let value = f(&r.borrow(), &map);
+                (value, slot.used.get())
+            });
+
+            values.push((version, generation, value));
+        }
+
+        // `f` might have used the target itself
+        let state = &target.state;
+        assert!(state.last.get() == 0 && state.len.get() == 0, "Target pool has been used!");
+        state.first_free.set(last as i32);
+        state.last.set(last as i32);
+        state.clock.set(self.state.clock.get());
+
+        // Free slots are pushed from the back so that the lowest indices are reused first, like in the original pool
+        for (i, (version, generation, value)) in values.into_iter().enumerate().rev() {
+            let slot = unsafe { target.slot_unchecked(i) };
+            slot.generation.set(generation);
+
+            if let Some((value, used)) = value {
+                unsafe { *slot.elem.get() = Some(value) };
+                slot.version.set(version);
+                slot.used.set(used);
+                state.len.add(1);
+                state.emit(PoolEvent::Insert(slot.key()));
+            } else if version == RETIRED_VERSION {
+                slot.version.set(RETIRED_VERSION);
+                state.retired.add(1);
+            } else if version == REMOVED_VERSION {
+                // The slot would have been freed when the last strong reference was dropped
+                state.free(slot);
+            } else {
+                let ff = state.first_free.get();
+                state.first_free.set(i as i32);
+                slot.version.set(-ff - 1);
+            }
+        }
+
+        if state.ordered {
+            // Keep the insertion order of this pool if it has one, anything else is added in slot order
+            let mut index = if self.state.ordered { self.state.head.get() } else { NO_SLOT };
+
+            while index != NO_SLOT {
+                if (index as usize) < last {
+                    let slot = unsafe { target.slot_unchecked(index as usize) };
+
+                    if slot.version.get() >= 0 && !target.is_linked(slot) {
+                        target.link(slot, state.tail.get(), NO_SLOT);
+                    }
+                }
+
+                index = unsafe { self.slot_unchecked(index as usize) }.next.get();
+            }
+
+            for slot in target.live_slots().filter(|slot| !target.is_linked(slot)) {
+                target.link(slot, state.tail.get(), NO_SLOT);
+            }
+        }
+    }
+
+    /// Returns the first free slot, if there is none room is made according to the overflow policy
+    fn make_room(&self) -> Option<&Slot<T>> {
+        // Evicting an element whose slot is retired doesn't free a slot, so this might take several attempts
+        while self.state.first_free.get() as usize >= self.capacity() {
+            let ok = match self.overflow {
+                OverflowPolicy::Reject => false,
+                OverflowPolicy::Grow => self.grow(),
+                OverflowPolicy::EvictLru => self.evict_lru(),
+            };
+
+            if !ok {
+                return None;
+            }
+        }
+
+        self.slots.slot(self.state.first_free.get() as usize)
+    }
+
+    fn evict_lru(&self) -> bool {
+        let clock = self.state.clock.get();
+
+        let lru = (0..self.state.last.get() as usize)
+            .map(|i| unsafe { self.slot_unchecked(i) })
+            .filter(|slot| slot.version.get() >= 0 && slot.ref_count.get() == 0)
+            .max_by_key(|slot| clock.wrapping_sub(slot.used.get()));
+
+        if let Some(slot) = lru {
+            self.remove_slot(slot);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Inserts an element in the first free slot, which must be `slot`
+    fn insert_at<'t>(&'t self, slot: &'t Slot<T>, f: impl FnOnce(WeakRef<'t, T>) -> T) -> StrongRef<'t, T> {
+        let index = slot.index.get();
+        let v = slot.generation.get();
+        self.state.first_free.set(-slot.version.get() - 1);
+        self.state.last.set(self.state.last.get().max(index + 1));
+
+        // Drop an unused recycled element now that the slot is out of the free list
+        drop(unsafe { (*slot.elem.get()).take() });
+
+        // Give the slot back to the pool if `f` panics
+        let guard = FreeGuard {
+            slot,
+            pool: &self.state,
+        };
+        let value = f(WeakRef {
+            slot,
+            pool: &self.state,
+            version: v,
+        });
+        std::mem::forget(guard);
+
+        slot.version.set(v);
+        unsafe { *slot.elem.get() = Some(value) };
+        slot.ref_count.set(1);
+        slot.used.set(self.state.tick());
+        self.state.len.add(1);
+
+        if self.state.ordered {
+            self.unlink(slot);
+            self.link(slot, self.state.tail.get(), NO_SLOT);
+        }
+
+        self.state.emit(PoolEvent::Insert(slot.key()));
+        StrongRef {
+            slot,
+            pool: &self.state,
+        }
+    }
+
+    /// Removes the element, all weak references to it are invalidated immediately. If there are strong references
+    /// to the element it's dropped, and the slot reused, when the last of them is dropped.
+    ///
+    /// # Panics
+    ///
+    /// If `r` belongs to another pool.
+    pub fn remove(&self, r: &WeakRef<T>) {
+        self.check_owner(r.pool);
+
+        if r.is_valid() {
+            self.remove_slot(r.slot);
+        }
+    }
+
+    /// Removes the element and returns it instead of dropping it, all weak references to it are invalidated. Returns
+    /// `None` if the element has been removed or has strong references.
+    ///
+    /// # Panics
+    ///
+    /// If `r` belongs to another pool.
+    pub fn take(&self, r: &WeakRef<T>) -> Option<T> {
+        self.check_owner(r.pool);
+
+        if r.is_valid() && r.slot.ref_count.get() == 0 {
+            Some(self.take_slot(r.slot))
+        } else {
+            None
+        }
+    }
+
+    /// Checks the internal invariants of the pool, the free list, counters and slot states, and returns a description
+    /// of the first violation found
+    pub fn validate(&self) -> Result<(), String> {
+        let cap = self.capacity();
+        let last = self.state.last.get() as usize;
+        let mut free = vec![false; cap];
+        let mut index = self.state.first_free.get();
+
+        if last > cap {
+            return Err(format!("Last index {last} is larger than capacity {cap}!"));
+        }
+
+        while index as usize != cap {
+            let slot = self.slots.slot(index as usize).filter(|_| (index as usize) < cap);
+            let slot = slot.ok_or_else(|| format!("Free list links to invalid slot {index}!"))?;
+
+            if std::mem::replace(&mut free[index as usize], true) {
+                return Err(format!("Free list has a cycle at slot {index}!"));
+            }
+
+            let version = slot.version.get();
+
+            if version >= 0 || version == REMOVED_VERSION || version == RETIRED_VERSION {
+                return Err(format!("Slot {index} is in the free list but isn't free!"));
+            }
+
+            index = -slot.version.get() - 1;
+        }
+
+        let mut len = 0;
+        let mut retired = 0;
+
+        for (i, is_free) in free.into_iter().enumerate() {
+            let slot = unsafe { self.slot_unchecked(i) };
+            let version = slot.version.get();
+            let has_elem = unsafe { (*slot.elem.get()).is_some() };
+
+            if slot.index.get() as usize != i {
+                return Err(format!("Slot {i} has index {}!", slot.index.get()));
+            }
+
+            if is_free {
+                if (has_elem && self.state.recycle.is_none()) || slot.ref_count.get() != 0 || slot.borrows.get() != 0 {
+                    return Err(format!("Free slot {i} is in use!"));
+                }
+            } else if version >= 0 || version == REMOVED_VERSION {
+                if i >= last {
+                    return Err(format!("Used slot {i} is after last index {last}!"));
+                }
+
+                if !has_elem {
+                    return Err(format!("Used slot {i} has no element!"));
+                }
+
+                if slot.borrows.get() != 0 && slot.ref_count.get() == 0 {
+                    return Err(format!("Slot {i} is borrowed without strong references!"));
+                }
+
+                if version == REMOVED_VERSION && slot.ref_count.get() == 0 {
+                    return Err(format!("Removed slot {i} has no strong references!"));
+                }
+
+                if version >= 0 {
+                    len += 1;
+                }
+            } else if version == RETIRED_VERSION {
+                if has_elem || slot.ref_count.get() != 0 || slot.borrows.get() != 0 {
+                    return Err(format!("Retired slot {i} is in use!"));
+                }
+
+                retired += 1;
+            } else {
+                return Err(format!("Free slot {i} isn't in the free list!"));
+            }
+        }
+
+        if len != self.len() {
+            return Err(format!("Length is {} but there are {len} elements!", self.len()));
+        }
+
+        if retired != self.state.retired.get() {
+            return Err(format!("{} slots should be retired but {retired} are!", self.state.retired.get()));
+        }
+
+        self.validate_order()
+    }
+
+    /// Checks that the insertion order list is well formed and contains all elements if the pool is ordered
+    fn validate_order(&self) -> Result<(), String> {
+        let cap = self.capacity();
+        let mut linked = vec![false; cap];
+        let (mut prev, mut index) = (NO_SLOT, self.state.head.get());
+
+        if !self.state.ordered && index != NO_SLOT {
+            return Err("Insertion order list isn't empty in an unordered pool!".to_owned());
+        }
+
+        while index != NO_SLOT {
+            if index < 0 || index as usize >= cap {
+                return Err(format!("Insertion order list links to invalid slot {index}!"));
+            }
+
+            if std::mem::replace(&mut linked[index as usize], true) {
+                return Err(format!("Insertion order list has a cycle at slot {index}!"));
+            }
+
+            let slot = unsafe { self.slot_unchecked(index as usize) };
+
+            if slot.prev.get() != prev {
+                return Err(format!("Slot {index} links back to {} instead of {prev}!", slot.prev.get()));
+            }
+
+            (prev, index) = (index, slot.next.get());
+        }
+
+        if self.state.tail.get() != prev {
+            return Err(format!("Insertion order list ends at {prev} but its tail is {}!", self.state.tail.get()));
+        }
+
+        for (i, linked) in linked.into_iter().enumerate() {
+            let slot = unsafe { self.slot_unchecked(i) };
+
+            if !linked && (slot.prev.get() != NO_SLOT || slot.next.get() != NO_SLOT) {
+                return Err(format!("Slot {i} has links but isn't in the insertion order list!"));
+            }
+
+            if self.state.ordered && slot.version.get() >= 0 && !linked {
+                return Err(format!("Element {i} is missing from the insertion order list!"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes all elements for which `f` returns false, see [`RcPool::remove`]
+    pub fn retain(&self, mut f: impl FnMut(&T) -> bool) { self.remove_where(|e| !f(e)); }
+
+    /// Removes all elements for which `f` returns true and returns the number of removed elements, see
+    /// [`RcPool::remove`]
+    pub fn remove_where(&self, mut f: impl FnMut(&T) -> bool) -> usize {
+        let mut count = 0;
+
+        for i in 0..self.state.last.get() as usize {
+            let slot = unsafe { self.slot_unchecked(i) };
+            let version = slot.version.get();
+
+            if version < 0 {
+                continue;
+            }
+
+            // Release the temporary reference before removing, so that it doesn't drop an unreferenced element later
+            let remove = f(&StrongRef::acquire(slot, &self.state).borrow());
+
+            // `f` might have removed the element itself, or even replaced it
+            if remove && slot.version.get() == version {
+                self.remove_slot(slot);
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    /// Removes all elements and returns the ones without strong references. Elements that are still referenced are
+    /// removed like with [`RcPool::remove`] and not returned. Elements not iterated over when the iterator is dropped
+    /// are removed too.
+    pub fn drain(&self) -> Drain<'_, T, A, I, G> {
+        Drain {
+            pool:  self,
+            index: 0,
+            end:   self.state.last.get() as usize,
+        }
+    }
+
+    /// Moves all elements to the lowest free slots so that iteration doesn't have to skip over removed ones, and
+    /// returns the old and new keys of the moved elements. Keys of elements that weren't moved stay valid while the
+    /// old keys of moved elements are invalidated. Elements with leaked strong references aren't moved.
+    pub fn compact(&mut self) -> HashMap<PoolKey<I, G>, PoolKey<I, G>> {
+        let is_free = |slot: &Slot<T>| {
+            let v = slot.version.get();
+            v < 0 && v != REMOVED_VERSION && v != RETIRED_VERSION
+        };
+
+        let last = self.state.last.get() as usize;
+        let mut remap = HashMap::new();
+        let (mut lo, mut hi) = (0, last);
+
+        loop {
+            while lo < hi && !is_free(unsafe { self.slot_unchecked(lo) }) {
+                lo += 1;
+            }
+
+            while hi > lo && self.live_slot(hi - 1).is_none_or(|slot| slot.ref_count.get() > 0) {
+                hi -= 1;
+            }
+
+            if hi <= lo {
+                break;
+            }
+
+            hi -= 1;
+            let (from, to) = unsafe { (self.slot_unchecked(hi), self.slot_unchecked(lo)) };
+            unsafe { *to.elem.get() = (*from.elem.get()).take() };
+            to.version.set(to.generation.get());
+
+            // The moved element keeps its place in the insertion order
+            if self.is_linked(from) {
+                self.unlink(to);
+                let (prev, next) = (from.prev.get(), from.next.get());
+                self.unlink(from);
+                self.link(to, prev, next);
+            }
+
+            to.used.set(from.used.get());
+            remap.insert(PoolKey::new(hi as i32, from.version.get()), PoolKey::new(lo as i32, to.version.get()));
+            self.state.emit(PoolEvent::Move {
+                from: from.key(),
+                to:   to.key(),
+            });
+
+            // Give `from` a new generation or retire it like `PoolState::free`, the free list is rebuilt below
+            if from.generation.get() >= self.state.max_generation {
+                from.version.set(RETIRED_VERSION);
+                self.state.retired.add(1);
+            } else {
+                from.generation.add(1);
+                from.version.set(-1);
+            }
+        }
+
+        // Slots from `last` on have never been used and are still linked in order
+        self.state.first_free.set(last as i32);
+        self.state.last.set(0);
+
+        for i in (0..last).rev() {
+            let slot = unsafe { self.slot_unchecked(i) };
+
+            if slot.version.get() < 0 && slot.version.get() != REMOVED_VERSION {
+                self.unlink(slot);
+            }
+
+            if is_free(slot) {
+                slot.version.set(-self.state.first_free.get() - 1);
+                self.state.first_free.set(i as i32);
+            } else if slot.version.get() != RETIRED_VERSION && self.state.last.get() == 0 {
+                self.state.last.set(i as i32 + 1);
+            }
+        }
+
+        remap
+    }
+
+    /// Removes the element from an unreferenced slot and returns it
+    fn take_slot(&self, slot: &Slot<T>) -> T {
+        debug_assert_eq!(slot.ref_count.get(), 0);
+        self.state.emit(PoolEvent::Remove(slot.key()));
+        self.unlink(slot);
+        self.state.free(slot);
+        unsafe { (*slot.elem.get()).take().unwrap_unchecked() }
+    }
+
+    fn remove_slot(&self, slot: &Slot<T>) {
+        self.state.emit(PoolEvent::Remove(slot.key()));
+
+        if slot.ref_count.get() > 0 {
+            self.state.len.sub(1);
+            slot.version.set(REMOVED_VERSION);
+        } else {
+            self.unlink(slot);
+            self.state.free(slot);
+            slot.drop_elem(&self.state);
+        }
+    }
+}
+
+/// Iterator returned by [`RcPool::iter`]
+pub struct Iter<'t, T, A: SlotStorage<T>, I: Index = u32, G: Index = u32> {
+    pool:  &'t RcPool<T, A, I, G>,
+    index: usize,
+    end:   usize,
+    next:  Option<StrongRef<'t, T>>,
+}
+
+impl<'t, T, A: SlotStorage<T>, I: Index, G: Index> Iterator for Iter<'t, T, A, I, G> {
+    type Item = StrongRef<'t, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let state = &self.pool.state;
+
+        if !state.ordered {
+            while self.index < self.end {
+                let slot = unsafe { self.pool.slot_unchecked(self.index) };
+                self.index += 1;
+
+                if slot.version.get() >= 0 {
+                    return Some(StrongRef::new(slot, state));
+                }
+            }
+
+            return None;
+        }
+
+        // Removed elements stay in the list while they're referenced, so the next one can always be found
+        loop {
+            let r = self.next.take()?;
+            let next = self.pool.next_linked(r.slot.next.get());
+            self.next = next.map(|slot| StrongRef::acquire(slot, state));
+
+            if r.slot.version.get() >= 0 {
+                r.slot.used.set(state.tick());
+                return Some(r);
+            }
+        }
+    }
+}
+
+/// Iterator returned by [`RcPool::drain`]
+pub struct Drain<'t, T, A: SlotStorage<T>, I: Index = u32, G: Index = u32> {
+    pool:  &'t RcPool<T, A, I, G>,
+    index: usize,
+    end:   usize,
+}
+
+impl<'t, T, A: SlotStorage<T>, I: Index, G: Index> Iterator for Drain<'t, T, A, I, G> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.end {
+            let slot = unsafe { self.pool.slot_unchecked(self.index) };
+            self.index += 1;
+
+            if slot.version.get() >= 0 {
+                if slot.ref_count.get() == 0 {
+                    return Some(self.pool.take_slot(slot));
+                }
+
+                self.pool.remove_slot(slot);
+            }
+        }
+
+        None
+    }
+}
+
+impl<'t, T, A: SlotStorage<T>, I: Index, G: Index> Drop for Drain<'t, T, A, I, G> {
+    fn drop(&mut self) { self.for_each(drop) }
+}
+
+impl<T: Clear, A: SlotStorage<T>, I: Index, G: Index> Clear for RcPool<T, A, I, G> {
+    fn clear(&self) {
+        for i in 0..self.state.last.get() {
+            let slot = unsafe { self.slot_unchecked(i as usize) };
+
+            if slot.version.get() >= 0 {
+                self.remove_slot(slot);
+            }
+        }
+    }
+}
+
+impl<T: Debug, A: SlotStorage<T>, I: Index, G: Index> Debug for RcPool<T, A, I, G> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elements: Vec<_> = self.live_slots().map(|slot| StrongRef::acquire(slot, &self.state)).collect();
+
+        f.debug_struct("RcPool")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("elements", &elements)
+            .finish()
+    }
+}
+
+impl<T, A: SlotStorage<T> + Default, I: Index, G: Index> Default for RcPool<T, A, I, G> {
+    fn default() -> Self { Self::new(A::default()) }
+}
+
+#[test]
+fn test_chunked_grow() {
+    let pool = ChunkedRcPool::<i32, 2>::new_chunked(0);
+    let a = pool.insert(1).unwrap();
+    let w = a.weak();
+    let refs: Vec<_> = (2..10).map(|i| pool.insert(i).unwrap()).collect();
+
+    assert_eq!(pool.capacity(), 10);
+    assert_eq!(*a.borrow(), 1);
+    assert_eq!(*w.get().unwrap().borrow(), 1);
+    assert_eq!(pool.iter().map(|r| *r.borrow()).collect::<Vec<_>>(), (1..10).collect::<Vec<_>>());
+    drop(refs);
+}
+
+#[test]
+fn test_pool_key() {
+    let pool = VecRcPool::new_vec(4);
+    let key = pool.insert("a").unwrap().key();
+    assert_eq!(*pool.upgrade_key(key).unwrap().borrow(), "a");
+
+    let a = pool.upgrade_key(key).unwrap();
+    pool.remove(&a.weak());
+    assert_eq!(a.key(), key);
+    drop(a);
+    let _b = pool.insert("b").unwrap();
+    assert!(pool.get_by_key(key).is_none());
+}
+
+#[test]
+fn test_compact_key() {
+    let pool = RcPool::<_, ChunkedSlots<_, 100>, u8, u8>::new_chunked(0);
+    let refs: Vec<_> = (0..256).map(|i| pool.insert(i).unwrap()).collect();
+    assert!(pool.insert(256).is_none());
+    assert_eq!(pool.capacity(), 256);
+
+    let key: PoolKey<u8, u8> = refs[255].key();
+    assert_eq!(std::mem::size_of_val(&key), 2);
+    assert_eq!(*pool.upgrade_key(key).unwrap().borrow(), 255);
+}
+
+#[test]
+fn test_generation_retire() {
+    let pool = RcPool::<_, Vec<_>, u8, u8>::new((0..1).map(|_| Default::default()).collect());
+    let mut keys = Vec::new();
+    let mut weaks = Vec::new();
+
+    for i in 0..256 {
+        let r = pool.insert(i).unwrap();
+        assert!(keys.iter().all(|k| pool.upgrade_key(*k).is_none()));
+        assert!(weaks.iter().all(|w: &WeakRef<_>| w.get().is_none()));
+        keys.push(r.key());
+        weaks.push(r.weak());
+        drop(r);
+        pool.remove(weaks.last().unwrap());
+    }
+
+    // The only slot used all its generations and is never handed out again
+    assert!(pool.insert(256).is_none());
+    assert!(keys.iter().all(|k| pool.upgrade_key(*k).is_none()));
+    assert!(weaks.iter().all(|w| w.get().is_none()));
+    assert_eq!(pool.stats().retired, 1);
+    assert_eq!(pool.stats().free, 0);
+    pool.validate().unwrap();
+}
+
+#[test]
+fn test_all_retired() {
+    let pool = RcPool::<_, Vec<_>, u8, u8>::new((0..3).map(|_| Default::default()).collect());
+    let mut count = 0;
+
+    while let Some(r) = pool.insert(count) {
+        pool.remove(&r.weak());
+        count += 1;
+    }
+
+    assert_eq!(count, 3 * 256);
+    assert_eq!(pool.stats().retired, 3);
+    assert!(pool.insert(0).is_none());
+    pool.validate().unwrap();
+}
+
+#[test]
+fn test_evict_retired() {
+    let pool = RcPool::<_, Vec<_>, u8, u8>::new((0..1).map(|_| Default::default()).collect())
+        .with_overflow(OverflowPolicy::EvictLru);
+
+    for i in 0..256 {
+        assert!(pool.insert(i).is_some());
+    }
+
+    assert!(pool.insert(256).is_none());
+    assert_eq!(pool.stats().retired, 1);
+    assert!(pool.validate().is_ok());
+}
+
+#[test]
+fn test_drop_pool() {
+    let rc = std::rc::Rc::new(());
+
+    {
+        let pool = VecRcPool::new_vec(4);
+        let a = pool.insert(rc.clone()).unwrap().weak();
+        let _ = pool.insert(rc.clone()).unwrap();
+        pool.remove(&a);
+        let _ = pool.insert(rc.clone()).unwrap();
+        assert_eq!(std::rc::Rc::strong_count(&rc), 3);
+    }
+
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "outstanding references")]
+fn test_drop_pool_leaked_ref() {
+    let pool = VecRcPool::new_vec(4);
+    std::mem::forget(pool.insert(1).unwrap());
+}
+
+#[test]
+#[should_panic(expected = "used with pool")]
+fn test_wrong_pool() {
+    let a = VecRcPool::new_vec(1);
+    let b = VecRcPool::new_vec(1);
+    let r = a.insert(1).unwrap().weak();
+    assert_ne!(r.pool_id(), b.id());
+    b.remove(&r);
+}
+
+#[test]
+fn test_deferred_remove() {
+    let rc = std::rc::Rc::new(());
+    let pool = VecRcPool::new_vec(1);
+    let a = pool.insert(rc.clone()).unwrap();
+    let b = a.clone();
+    let w = a.weak();
+
+    pool.remove(&w);
+    assert!(w.get().is_none());
+    assert!(pool.iter().next().is_none());
+    drop(a);
+    assert_eq!(std::rc::Rc::strong_count(&rc), 2);
+    assert!(pool.insert(rc.clone()).is_none());
+
+    drop(b);
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+    assert!(pool.insert(rc.clone()).is_some());
+}
+
+#[test]
+fn test_auto_remove() {
+    let rc = std::rc::Rc::new(());
+    let pool = VecRcPool::new_vec(1).with_auto_remove();
+    let a = pool.insert(rc.clone()).unwrap();
+    let w = a.weak();
+    let b = w.get().unwrap();
+
+    drop(a);
+    assert!(w.get().is_some());
+    drop(b);
+    assert!(w.get().is_none());
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+    assert!(pool.insert(rc.clone()).is_some());
+}
+
+#[test]
+#[should_panic(expected = "without strong references")]
+fn test_auto_remove_not_empty() {
+    let pool = VecRcPool::new_vec(2);
+    let _ = pool.insert(1).unwrap();
+    let _ = pool.with_auto_remove();
+}
+
+#[test]
+fn test_borrow() {
+    let pool = VecRcPool::new_vec(1);
+    let a = pool.insert(1).unwrap();
+    let b = a.clone();
+
+    *a.borrow_mut() += 1;
+    let r = b.borrow();
+    assert!(a.try_borrow_mut().is_none());
+    assert_eq!(*a.borrow() + *r, 4);
+    drop(r);
+    assert!(b.try_borrow_mut().is_some());
+}
+
+#[test]
+fn test_map() {
+    let pool = VecRcPool::new_vec(1);
+    let a = pool.insert((1, String::from("a"))).unwrap();
+    let w = a.weak().map(|e| e.1.as_str());
+
+    RefMut::map(a.borrow_mut(), |e| &mut e.1).push('b');
+    let m = a.clone().map(|e| &e.0);
+    assert!(a.try_borrow_mut().is_none());
+    assert_eq!(*m, 1);
+    assert_eq!(&*w.get().unwrap(), "ab");
+
+    drop(m);
+    pool.remove(&a.weak());
+    assert!(w.get().is_none());
+}
+
+#[test]
+fn test_insert_cyclic() {
+    struct Node<'t> {
+        this: WeakRef<'t, Node<'t>>,
+    }
+
+    let pool = VecRcPool::new_vec(2);
+    let a = pool.insert_cyclic(|this| {
+        assert!(this.get().is_none());
+        Node { this }
+    });
+
+    let a = a.unwrap();
+    assert!(a.borrow().this.get().is_some());
+    assert!(a.borrow().this == a.weak());
+}
+
+#[test]
+fn test_clone_into() {
+    use crate::cell_set::ArrayCellSet;
+
+    struct Node<'t> {
+        value:   i32,
+        friends: ArrayCellSet<WeakRef<'t, Node<'t>>, 4>,
+    }
+
+    impl<'t, 'u> RemapRefs<'u> for Node<'t> {
+        type Output = Node<'u>;
+
+        fn remap_refs(&self, map: &RefMap<'u, Node<'u>>) -> Node<'u> {
+            let friends = ArrayCellSet::default();
+            self.friends.iter().for_each(|r| _ = friends.insert(map.weak(&r)));
+            Node {
+                value: self.value,
+                friends,
+            }
+        }
+    }
+
+    fn node<'t>(value: i32) -> Node<'t> {
+        Node {
+            value,
+            friends: Default::default(),
+        }
+    }
+
+    let pool = VecRcPool::new_vec(4);
+    let a = pool.insert(node(1)).unwrap();
+    let b = pool.insert(node(2)).unwrap();
+    let c = pool.insert(node(3)).unwrap();
+    let _ = a.borrow().friends.insert(b.weak());
+    let _ = a.borrow().friends.insert(c.weak());
+    let _ = b.borrow().friends.insert(a.weak());
+    let c_key = c.key::<u32, u32>();
+    drop(c);
+    pool.remove(&pool.get_weak(2).unwrap());
+
+    let clone = ChunkedRcPool::<_, 2>::new_chunked(0);
+    pool.clone_into(&clone);
+    clone.validate().unwrap();
+    assert_eq!(clone.len(), 2);
+    assert!(clone.upgrade_key(c_key).is_none());
+    assert_eq!(clone.get_by_key(a.key()).unwrap().get().unwrap().borrow().value, 1);
+
+    let a2 = clone.get(0).unwrap();
+    let friends: Vec<_> = a2.borrow().friends.iter().map(|r| r.get().map(|r| r.borrow().value)).collect();
+    assert_eq!(friends, [Some(2), None]);
+    assert_eq!(a2.borrow().friends.iter().next().unwrap().pool_id(), clone.id());
+
+    // The removed slot is reused first, with a version the stale reference doesn't match
+    a.borrow_mut().value = 10;
+    assert_eq!(clone.insert(node(4)).unwrap().index(), 2);
+    assert!(clone.upgrade_key(c_key).is_none());
+    assert_eq!(a2.borrow().value, 1);
+}
+
+#[test]
+#[should_panic(expected = "removes unreferenced elements")]
+fn test_clone_into_auto_remove() {
+    let pool = VecRcPool::new_vec(1);
+    let _a = pool.insert(1).unwrap();
+    pool.clone_into_with(&VecRcPool::new_vec(1).with_auto_remove(), |e, _| *e);
+}
+
+#[test]
+#[should_panic(expected = "has been used")]
+fn test_clone_into_used_by_f() {
+    let pool = VecRcPool::new_vec(1);
+    let _a = pool.insert(1).unwrap();
+    let target = VecRcPool::new_vec(2);
+    let stash = RefCell::new(Vec::new());
+
+    pool.clone_into_with(&target, |e, _| {
+        stash.borrow_mut().push(target.insert(100).unwrap());
+        *e
+    });
+}
+
+#[test]
+fn test_overflow() {
+    let pool = VecRcPool::new_vec(2).with_overflow(OverflowPolicy::EvictLru);
+    let a = pool.insert("a").unwrap().key();
+    let b = pool.insert("b").unwrap().key();
+    let _ = pool.upgrade_key(a);
+
+    let c = pool.insert("c").unwrap();
+    assert!(pool.upgrade_key(a).is_some());
+    assert!(pool.upgrade_key(b).is_none());
+
+    let a = pool.upgrade_key(a);
+    assert_eq!(pool.try_insert("d").err().unwrap().into_inner(), "d");
+    drop((a, c));
+
+    let pool = RcPool::<_, ChunkedSlots<_, 1>>::new_chunked(1).with_overflow(OverflowPolicy::Reject);
+    let _a = pool.insert(1).unwrap();
+    assert!(pool.insert(2).is_none());
+}
+
+#[test]
+fn test_retain_drain() {
+    let pool = VecRcPool::new_vec(8);
+
+    for i in 0..8 {
+        let _ = pool.insert(i).unwrap();
+    }
+
+    let r = pool.iter().nth(3).unwrap();
+    pool.retain(|e| e % 2 == 1);
+    assert_eq!(*r.borrow(), 3);
+    assert_eq!(pool.remove_where(|e| *e == 3), 1);
+    assert!(r.weak().get().is_none());
+
+    assert_eq!(pool.len(), 3);
+
+    let mut drained: Vec<_> = pool.drain().collect();
+    drained.sort();
+    assert_eq!(drained, [1, 5, 7]);
+    drop(r);
+    assert!(pool.is_empty());
+    assert!(pool.iter().next().is_none());
+
+    // A predicate that removes its own element
+    let w = pool.insert(0).unwrap().weak();
+    let removed = pool.remove_where(|_| {
+        pool.remove(&w);
+        true
+    });
+    assert_eq!(removed, 0);
+    pool.validate().unwrap();
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn test_compact() {
+    let mut pool = VecRcPool::new_vec(8);
+    let keys: Vec<PoolKey> = (0..8).map(|i| pool.insert(i).unwrap().key()).collect();
+
+    for i in [0, 2, 3, 6] {
+        pool.remove(&pool.get_by_key(keys[i]).unwrap());
+    }
+
+    let remap = pool.compact();
+    pool.validate().unwrap();
+    assert_eq!(remap.len(), 3);
+    assert_eq!(pool.stats().last, 4);
+    assert!(pool.upgrade_key(keys[7]).is_none());
+    assert_eq!(*pool.upgrade_key(remap[&keys[7]]).unwrap().borrow(), 7);
+    assert_eq!(remap[&keys[7]].index(), 0);
+    assert_eq!(*pool.upgrade_key(remap[&keys[5]]).unwrap().borrow(), 5);
+    assert_eq!(*pool.upgrade_key(keys[1]).unwrap().borrow(), 1);
+
+    let mut values: Vec<_> = pool.iter().map(|r| *r.borrow()).collect();
+    values.sort();
+    assert_eq!(values, [1, 4, 5, 7]);
+    assert_eq!(pool.insert(8).unwrap().index(), 4);
+}
+
+#[test]
+fn test_insertion_order() {
+    // The cost of the links, documented on `with_insertion_order`
+    assert_eq!(std::mem::size_of::<SlotHeader>(), 32);
+    let mut pool = VecRcPool::new_vec(8).with_insertion_order();
+    let values = |pool: &VecRcPool<_>| pool.iter().map(|r| *r.borrow()).collect::<Vec<_>>();
+    let weak: Vec<_> = ["a", "b", "c", "d"].map(|v| pool.insert(v).unwrap().weak()).into();
+
+    pool.remove(&weak[1]);
+    assert_eq!(pool.insert("e").unwrap().index(), 1);
+    assert_eq!(values(&pool), ["a", "c", "d", "e"]);
+
+    assert!(pool.move_to_front(&weak[3]));
+    assert!(pool.move_to_back(&weak[0]));
+    assert!(!pool.move_to_back(&weak[1]));
+    assert_eq!(values(&pool), ["d", "c", "e", "a"]);
+    assert_eq!(*pool.front().unwrap().borrow(), "d");
+    assert_eq!(*pool.back().unwrap().borrow(), "a");
+    pool.validate().unwrap();
+
+    // Removing the element after the current one while iterating
+    let mut seen = Vec::new();
+
+    for r in pool.iter() {
+        seen.push(*r.borrow());
+
+        if *r.borrow() == "c" {
+            pool.remove(&pool.get_weak(1).unwrap());
+        }
+    }
+
+    assert_eq!(seen, ["d", "c", "a"]);
+    pool.validate().unwrap();
+
+    let _ = pool.compact();
+    pool.validate().unwrap();
+    assert_eq!(values(&pool), ["d", "c", "a"]);
+
+    // Slots freed by dropping their last reference stay in the list until they're reused
+    let pool = VecRcPool::new_vec(4).with_auto_remove().with_insertion_order();
+    let a = pool.insert("a").unwrap();
+    drop(pool.insert("b").unwrap());
+    let _c = pool.insert("c").unwrap();
+    drop(a);
+    pool.validate().unwrap();
+    let _d = pool.insert("d").unwrap();
+    assert_eq!(values(&pool), ["c", "d"]);
+    pool.validate().unwrap();
+}
+
+#[test]
+fn test_take() {
+    let rc = std::rc::Rc::new(());
+    let pool = VecRcPool::new_vec(4);
+    let a = pool.insert(rc.clone()).unwrap();
+    let w = a.weak();
+
+    assert!(pool.take(&w).is_none());
+    drop(a);
+    let taken = pool.take(&w).unwrap();
+    assert!(w.get().is_none());
+    assert!(pool.take(&w).is_none());
+    assert_eq!(std::rc::Rc::strong_count(&rc), 2);
+    drop(taken);
+
+    let b = pool.insert(rc.clone()).unwrap();
+    let c = b.clone();
+    assert!(b.into_inner().is_none());
+    assert_eq!(pool.len(), 1);
+    let w = c.weak();
+    let taken = c.into_inner().unwrap();
+    assert!(w.get().is_none());
+    assert!(pool.is_empty());
+    assert_eq!(std::rc::Rc::strong_count(&rc), 2);
+    drop(taken);
+
+    // The last reference to a removed element gets it back instead of dropping it
+    let d = pool.insert(rc.clone()).unwrap();
+    pool.remove(&d.weak());
+    assert!(d.into_inner().is_some());
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+    pool.validate().unwrap();
+}
+
+#[test]
+fn test_recycling() {
+    let pool = VecRcPool::<Vec<u8>>::new_vec(2).with_recycling();
+    let a = pool.insert_with(|v| v.extend([1, 2, 3])).unwrap();
+    let ptr = a.borrow().as_ptr();
+    let w = a.weak();
+    drop(a);
+    pool.remove(&w);
+    pool.validate().unwrap();
+
+    let b = pool.insert_with(|v| {
+        assert!(v.is_empty());
+        v.push(4);
+    });
+
+    assert_eq!(*b.as_ref().unwrap().borrow(), [4]);
+    assert_eq!(b.unwrap().borrow().as_ptr(), ptr);
+
+    // Elements removed while referenced are recycled when the last reference is dropped
+    let c = pool.insert_with(|v| v.push(5)).unwrap();
+    let ptr = c.borrow().as_ptr();
+    pool.remove(&c.weak());
+    drop(c);
+    assert_eq!(pool.insert_with(|_| {}).unwrap().borrow().as_ptr(), ptr);
+    pool.validate().unwrap();
+}
+
+#[test]
+fn test_events() {
+    let mut pool = VecRcPool::new_vec(4).with_events();
+    let a = pool.insert(1).unwrap();
+    let b = pool.insert(2).unwrap().weak();
+    let (ka, kb) = (a.key(), b.key());
+    assert_eq!(pool.take_events(), [PoolEvent::Insert(ka), PoolEvent::Insert(kb)]);
+
+    pool.remove(&a.weak());
+    assert_eq!(pool.take_events(), [PoolEvent::Remove(ka)]);
+    drop(a);
+    assert_eq!(pool.take_events(), [PoolEvent::Drop(ka)]);
+    assert!(pool.take_events().is_empty());
+
+    let remap = pool.compact();
+    let to = remap[&kb];
+    assert_eq!(pool.take_events(), [PoolEvent::Move { from: kb, to }]);
+    assert_eq!(pool.take(&pool.get_by_key(to).unwrap()), Some(2));
+    assert_eq!(pool.take_events(), [PoolEvent::Remove(to)]);
+
+    let kc = pool.insert(3).unwrap().key();
+    pool.retain(|_| false);
+    assert_eq!(pool.take_events(), [PoolEvent::Insert(kc), PoolEvent::Remove(kc)]);
+}
+
+#[test]
+fn test_exclusive_access() {
+    let mut pool = VecRcPool::new_vec(4);
+    let keys: Vec<PoolKey> = (0..3).map(|i| pool.insert(i).unwrap().key()).collect();
+    pool.remove(&pool.get_by_key(keys[1]).unwrap());
+
+    pool.iter_mut().for_each(|e| *e *= 10);
+    *pool.get_mut(keys[2]).unwrap() += 1;
+    assert!(pool.get_mut(keys[1]).is_none());
+
+    let [a, c] = pool.get_disjoint_mut([keys[0], keys[2]]).unwrap();
+    std::mem::swap(a, c);
+    assert!(pool.get_disjoint_mut([keys[0], keys[0]]).is_none());
+    assert!(pool.get_disjoint_mut([keys[0], keys[1]]).is_none());
+
+    let values: Vec<_> = pool.iter().map(|r| *r.borrow()).collect();
+    assert_eq!(values, [21, 0]);
+
+    let mut pool = VecRcPool::new_vec(4).with_insertion_order();
+    let a = pool.insert(1).unwrap().weak();
+    let _ = pool.insert(2).unwrap();
+    pool.move_to_back(&a);
+    assert_eq!(pool.iter_mut().map(|e| *e).collect::<Vec<_>>(), [2, 1]);
+}
+
+#[test]
+fn test_validate() {
+    let pool = ChunkedRcPool::<_, 4>::new_chunked(0);
+    let refs: Vec<_> = (0..10).map(|i| pool.insert(i).unwrap()).collect();
+    assert_eq!(pool.validate(), Ok(()));
+
+    pool.retain(|e| e % 3 == 0);
+    assert_eq!(pool.validate(), Ok(()));
+    drop(refs);
+    assert_eq!(pool.validate(), Ok(()));
+    assert_eq!(pool.len(), 4);
+    assert_eq!(pool.iter().count(), 4);
+
+    pool.state.len.set(3);
+    assert!(pool.validate().is_err());
+}
+
+#[test]
+fn test_index_access() {
+    let pool = VecRcPool::new_vec(4);
+    let other = VecRcPool::new_vec(4);
+    let a = pool.insert(1).unwrap();
+    let i = pool.index_of(&a).unwrap();
+
+    drop(pool.get(i).unwrap());
+    assert!(a.is_unique());
+    assert!(pool.contains_index(i));
+    assert!(!pool.contains_index(i + 1));
+    assert!(pool.get_weak(i).unwrap() == a.weak());
+    assert!(other.index_of(&a).is_none());
+}
+
+#[test]
+fn test_stats() {
+    let pool = VecRcPool::new_vec(4);
+    let a = pool.insert(1).unwrap();
+    let b = a.clone();
+    let _ = pool.insert(2).unwrap();
+    let c = pool.insert(3).unwrap();
+    pool.remove(&c.weak());
+
+    let stats = pool.stats();
+    assert_eq!((stats.len, stats.free, stats.removed, stats.last, stats.referenced), (2, 1, 1, 3, 2));
+    assert_eq!(stats.ref_counts, BTreeMap::from([(0, 1), (1, 1), (2, 1)]));
+    assert_eq!(
+        format!("{pool:?}"),
+        "RcPool { len: 2, capacity: 4, elements: [StrongRef { index: 0, version: 0, value: 1 }, \
+         StrongRef { index: 1, version: 0, value: 2 }] }"
+    );
+
+    let _m = b.borrow_mut();
+    assert_eq!(format!("{a:?}"), "StrongRef { index: 0, version: 0, value: <borrowed> }");
+}
+
+#[test]
+fn test_dyn_refs() {
+    use crate::cell_set::VecCellSet;
+
+    trait Component {
+        fn value(&self) -> i32;
+        fn bump(&mut self);
+    }
+
+    impl Component for i32 {
+        fn value(&self) -> i32 { *self }
+        fn bump(&mut self) { *self += 1 }
+    }
+
+    impl Component for String {
+        fn value(&self) -> i32 { self.len() as i32 }
+        fn bump(&mut self) { self.push('!') }
+    }
+
+    impl<T: Component + 'static> AsDyn<dyn Component> for T {
+        fn as_dyn(&self) -> &(dyn Component + 'static) { self }
+        fn as_dyn_mut(&mut self) -> &mut (dyn Component + 'static) { self }
+    }
+
+    let ints = VecRcPool::new_vec(4);
+    let strings = VecRcPool::new_vec(4);
+    let a = ints.insert(1).unwrap();
+    let b = strings.insert("ab".to_string()).unwrap();
+    let set = VecCellSet::new_vec(4);
+    assert!(set.insert(a.weak().into_dyn::<dyn Component>()).is_some());
+    assert!(set.insert(b.weak().into_dyn::<dyn Component>()).is_some());
+
+    set.iter_ref().for_each(|r| r.borrow_mut().bump());
+    assert_eq!(set.iter_ref().map(|r| r.borrow().value()).sum::<i32>(), 5);
+    assert_eq!(*b.borrow(), "ab!");
+    assert!(set.iter().any(|r| r.pool_id() == ints.id()));
+
+    let c = b.clone().into_dyn::<dyn Component>();
+    assert!(c.try_borrow_mut().is_some() && !b.is_unique());
+    strings.remove(&b.weak());
+    assert_eq!(set.iter_ref().count(), 1);
+    drop(b);
+    assert_eq!(c.borrow().value(), 3);
+    drop(c);
+    assert!(strings.is_empty() && strings.validate().is_ok());
+    drop(a);
 }
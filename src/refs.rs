@@ -1,21 +1,20 @@
-use std::{
-    ops::Deref,
-    rc::{Rc, Weak},
-};
+use std::rc::{Rc, Weak};
 
-pub trait StrongRefTrait: Deref {
+pub trait StrongRefTrait {
+    type Target: ?Sized;
     type Weak: WeakRefTrait<Target = Self::Target>;
     fn downgrade(&self) -> Self::Weak;
 }
 
 pub trait WeakRefTrait {
-    type Target;
+    type Target: ?Sized;
     type Strong: StrongRefTrait<Target = Self::Target>;
     fn upgrade(&self) -> Option<Self::Strong>;
     fn is_valid(&self) -> bool;
 }
 
 impl<T> StrongRefTrait for Rc<T> {
+    type Target = T;
     type Weak = Weak<T>;
     fn downgrade(&self) -> Self::Weak { Rc::downgrade(self) }
 }
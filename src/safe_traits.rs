@@ -6,7 +6,7 @@ use std::{
 
 use crate::{
     mcell::MCell,
-    rc_pool::{StrongRef, WeakRef},
+    rc_pool::{PoolKey, StrongRef, WeakRef},
 };
 
 /// # Safety
@@ -36,6 +36,7 @@ unsafe impl<T> SafeTraits for Rc<T> {}
 unsafe impl<T> SafeTraits for Weak<T> {}
 unsafe impl<'t, T> SafeTraits for StrongRef<'t, T> {}
 unsafe impl<'t, T> SafeTraits for WeakRef<'t, T> {}
+unsafe impl<I: SafeTraits, G: SafeTraits> SafeTraits for PoolKey<I, G> {}
 
 unsafe impl<T: SafeTraits> SafeTraits for Option<T> {}
 unsafe impl<T: SafeTraits> SafeTraits for Cell<T> {}
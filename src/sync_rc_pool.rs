@@ -0,0 +1,566 @@
+use crate::rc_pool::{InsertError, PoolId, PoolKey};
+use crate::refs::{StrongRefTrait, WeakRefTrait};
+use std::cell::UnsafeCell;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// Set in the slot state while the slot contains an element that hasn't been removed
+const LIVE: u64 = 1 << 31;
+
+/// Mask of the reference count in the slot state
+const REF_COUNT: u64 = LIVE - 1;
+
+/// Generation of a slot that is never reused because its generation would wrap around
+const RETIRED: u32 = u32::MAX;
+
+/// Largest generation a slot can have, the same as for [`RcPool`](crate::rc_pool::RcPool) so that keys of both pools
+/// have the same range
+const MAX_GENERATION: u32 = i32::MAX as u32;
+
+/// End of the free list
+const NO_SLOT: u32 = u32::MAX;
+
+#[must_use]
+fn pack(generation: u32, live: bool, ref_count: u64) -> u64 {
+    (generation as u64) << 32 | if live { LIVE } else { 0 } | ref_count
+}
+
+#[must_use]
+fn generation(state: u64) -> u32 { (state >> 32) as u32 }
+
+#[must_use]
+fn is_live(state: u64) -> bool { state & LIVE != 0 }
+
+/// Slot of a [`SyncRcPool`]. The generation, live flag and reference count share one atomic so that upgrading a weak
+/// reference can't race with the element being removed and the slot reused.
+pub struct SyncSlot<T> {
+    elem:  UnsafeCell<Option<T>>,
+    state: AtomicU64,
+    next:  AtomicU32,
+}
+
+// The element is only accessed through shared references while it's live, and dropped by whichever thread releases
+// the last reference to it
+unsafe impl<T: Send + Sync> Sync for SyncSlot<T> {}
+
+impl<T> SyncSlot<T> {
+    #[must_use]
+    unsafe fn get(&self) -> &T { (*self.elem.get()).as_ref().unwrap_unchecked() }
+
+    /// Increments the reference count if the slot is live and, if given, has the generation `version`
+    #[must_use]
+    fn acquire(&self, version: Option<u32>) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+
+        loop {
+            if !is_live(state) || version.is_some_and(|v| v != generation(state)) {
+                return false;
+            }
+
+            assert!(state & REF_COUNT < REF_COUNT / 2, "Too many references to pool element!");
+
+            match self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(s) => state = s,
+            }
+        }
+    }
+}
+
+impl<T> Default for SyncSlot<T> {
+    fn default() -> Self {
+        Self {
+            elem:  UnsafeCell::new(None),
+            state: Default::default(),
+            next:  Default::default(),
+        }
+    }
+}
+
+/// Shared part of a [`SyncRcPool`]. Whichever thread drops the last strong reference to a removed element pushes the
+/// slot back on the free list through it, so all of it is atomic.
+struct SyncPoolState {
+    id:      PoolId,
+    /// Index of the first free slot in the low half and a counter that changes on every update in the high half, so
+    /// that a slot that is popped and pushed back while another thread pops isn't mistaken for an unchanged list
+    free:    AtomicU64,
+    len:     AtomicUsize,
+    last:    AtomicUsize,
+    retired: AtomicUsize,
+}
+
+impl SyncPoolState {
+    #[must_use]
+    fn pop<T>(&self, slots: &[SyncSlot<T>]) -> Option<usize> {
+        let mut head = self.free.load(Ordering::Acquire);
+
+        loop {
+            let index = head as u32;
+
+            if index == NO_SLOT {
+                return None;
+            }
+
+            let next = slots[index as usize].next.load(Ordering::Relaxed);
+            let new = ((generation(head).wrapping_add(1) as u64) << 32) | next as u64;
+
+            match self.free.compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => return Some(index as usize),
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    fn push<T>(&self, index: u32, slot: &SyncSlot<T>) {
+        let mut head = self.free.load(Ordering::Relaxed);
+
+        loop {
+            slot.next.store(head as u32, Ordering::Relaxed);
+            let new = ((generation(head).wrapping_add(1) as u64) << 32) | index as u64;
+
+            match self.free.compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    /// Drops the element of a removed slot without references and gives the slot back to the pool, or retires it if
+    /// its generation can't be increased anymore
+    fn free<T>(&self, index: u32, slot: &SyncSlot<T>) {
+        fence(Ordering::Acquire);
+        drop(unsafe { (*slot.elem.get()).take() });
+        let g = generation(slot.state.load(Ordering::Relaxed));
+
+        if g >= MAX_GENERATION {
+            slot.state.store(pack(RETIRED, false, 0), Ordering::Release);
+            self.retired.fetch_add(1, Ordering::Relaxed);
+        } else {
+            slot.state.store(pack(g + 1, false, 0), Ordering::Release);
+            self.push(index, slot);
+        }
+    }
+
+    /// Clears the live flag of the slot if it still has the generation `version`. Only the thread that clears it
+    /// updates the length and frees the slot.
+    fn remove<T>(&self, index: u32, slot: &SyncSlot<T>, version: u32) {
+        let mut state = slot.state.load(Ordering::Relaxed);
+
+        loop {
+            if !is_live(state) || generation(state) != version {
+                return;
+            }
+
+            match slot.state.compare_exchange_weak(state, state & !LIVE, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(s) => state = s,
+            }
+        }
+
+        self.len.fetch_sub(1, Ordering::Relaxed);
+
+        if state & REF_COUNT == 0 {
+            self.free(index, slot);
+        }
+    }
+}
+
+/// Strong reference to an element of a [`SyncRcPool`], it can be sent to and shared between threads. Like `Arc` it
+/// only gives shared access to the element, which can use atomics or locks for mutable state.
+pub struct SyncStrongRef<'t, T> {
+    slot:  &'t SyncSlot<T>,
+    pool:  &'t SyncPoolState,
+    index: u32,
+}
+
+impl<'t, T> SyncStrongRef<'t, T> {
+    #[must_use]
+    pub fn weak(&self) -> SyncWeakRef<'t, T> {
+        SyncWeakRef {
+            slot:    self.slot,
+            pool:    self.pool,
+            index:   self.index,
+            version: generation(self.slot.state.load(Ordering::Relaxed)),
+        }
+    }
+
+    #[must_use]
+    pub fn key(&self) -> PoolKey { self.weak().key() }
+
+    /// Returns true if no other strong reference exists at the time of the call, another thread might still create
+    /// one from a weak reference right after
+    #[must_use]
+    pub fn is_unique(&self) -> bool { self.slot.state.load(Ordering::Acquire) & REF_COUNT == 1 }
+
+    /// Index of the slot this reference keeps alive
+    #[must_use]
+    pub fn index(&self) -> usize { self.index as usize }
+
+    /// Id of the [`SyncRcPool`] the element is in
+    #[must_use]
+    pub fn pool_id(&self) -> PoolId { self.pool.id }
+}
+
+impl<'t, T> Deref for SyncStrongRef<'t, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { self.slot.get() } }
+}
+
+impl<'t, T> Clone for SyncStrongRef<'t, T> {
+    fn clone(&self) -> Self {
+        let old = self.slot.state.fetch_add(1, Ordering::Relaxed);
+
+        if old & REF_COUNT >= REF_COUNT / 2 {
+            std::process::abort();
+        }
+
+        Self {
+            slot:  self.slot,
+            pool:  self.pool,
+            index: self.index,
+        }
+    }
+}
+
+impl<'t, T> Drop for SyncStrongRef<'t, T> {
+    fn drop(&mut self) {
+        let old = self.slot.state.fetch_sub(1, Ordering::Release);
+
+        if old & REF_COUNT == 1 && !is_live(old) {
+            self.pool.free(self.index, self.slot);
+        }
+    }
+}
+
+impl<'t, T: Debug> Debug for SyncStrongRef<'t, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncStrongRef")
+            .field("index", &self.index)
+            .field("version", &generation(self.slot.state.load(Ordering::Relaxed)))
+            .field("value", &**self)
+            .finish()
+    }
+}
+
+impl<'t, T> StrongRefTrait for SyncStrongRef<'t, T> {
+    type Target = T;
+    type Weak = SyncWeakRef<'t, T>;
+    fn downgrade(&self) -> Self::Weak { self.weak() }
+}
+
+/// Weak reference to an element of a [`SyncRcPool`], it can be sent to and shared between threads
+pub struct SyncWeakRef<'t, T> {
+    slot:    &'t SyncSlot<T>,
+    pool:    &'t SyncPoolState,
+    index:   u32,
+    version: u32,
+}
+
+impl<'t, T> SyncWeakRef<'t, T> {
+    #[must_use]
+    pub fn get(&self) -> Option<SyncStrongRef<'t, T>> { self.upgrade() }
+
+    #[must_use]
+    pub fn key(&self) -> PoolKey { PoolKey::new(self.index as i32, self.version as i32) }
+
+    /// Index of the slot, which might hold a newer element by now
+    #[must_use]
+    pub fn index(&self) -> usize { self.index as usize }
+
+    /// Id of the [`SyncRcPool`] the element was in
+    #[must_use]
+    pub fn pool_id(&self) -> PoolId { self.pool.id }
+}
+
+impl<'t, T> WeakRefTrait for SyncWeakRef<'t, T> {
+    type Target = T;
+    type Strong = SyncStrongRef<'t, T>;
+
+    fn upgrade(&self) -> Option<SyncStrongRef<'t, T>> {
+        self.slot.acquire(Some(self.version)).then(|| SyncStrongRef {
+            slot:  self.slot,
+            pool:  self.pool,
+            index: self.index,
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        let state = self.slot.state.load(Ordering::Acquire);
+        is_live(state) && generation(state) == self.version
+    }
+}
+
+impl<'t, T> Clone for SyncWeakRef<'t, T> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<'t, T> Copy for SyncWeakRef<'t, T> {}
+
+impl<'t, T> PartialEq for SyncWeakRef<'t, T> {
+    fn eq(&self, other: &Self) -> bool { std::ptr::eq(self.slot, other.slot) && self.version == other.version }
+}
+
+impl<'t, T> Eq for SyncWeakRef<'t, T> {}
+
+impl<'t, T> Hash for SyncWeakRef<'t, T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.slot as *const SyncSlot<T>).hash(state);
+        self.version.hash(state);
+    }
+}
+
+impl<'t, T> Debug for SyncWeakRef<'t, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncWeakRef").field("index", &self.index).field("version", &self.version).finish()
+    }
+}
+
+/// Pool of reference counted elements that can be shared between threads. Inserting and removing elements is lock
+/// free, the pool can't grow so it's created with all the slots it will ever use.
+pub struct SyncRcPool<T, A> {
+    slots:    A,
+    state:    SyncPoolState,
+    _phantom: PhantomData<fn(T) -> T>,
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Slot storage of a [`SyncRcPool`]. It's only implemented for storage the pool owns, so that two pools can never
+/// hand out the same slot.
+pub trait SyncSlotStorage<T>: sealed::Sealed + AsRef<[SyncSlot<T>]> {}
+
+impl<T> sealed::Sealed for Vec<SyncSlot<T>> {}
+impl<T> SyncSlotStorage<T> for Vec<SyncSlot<T>> {}
+impl<T, const CAP: usize> sealed::Sealed for [SyncSlot<T>; CAP] {}
+impl<T, const CAP: usize> SyncSlotStorage<T> for [SyncSlot<T>; CAP] {}
+
+pub type VecSyncRcPool<T> = SyncRcPool<T, Vec<SyncSlot<T>>>;
+pub type ArraySyncRcPool<T, const CAP: usize> = SyncRcPool<T, [SyncSlot<T>; CAP]>;
+
+impl<T> SyncRcPool<T, Vec<SyncSlot<T>>> {
+    #[must_use]
+    pub fn new_vec(cap: usize) -> Self {
+        let mut v = Vec::with_capacity(cap);
+        v.resize_with(v.capacity(), SyncSlot::default);
+        Self::new(v)
+    }
+}
+
+impl<T, A: SyncSlotStorage<T>> SyncRcPool<T, A> {
+    /// Maximum number of slots the pool can use
+    pub const MAX_CAPACITY: usize = NO_SLOT as usize;
+
+    #[must_use]
+    pub fn new(slots: A) -> Self {
+        let cap = slots.as_ref().len().min(Self::MAX_CAPACITY);
+
+        for (i, slot) in slots.as_ref()[..cap].iter().enumerate() {
+            slot.next.store(if i + 1 < cap { i as u32 + 1 } else { NO_SLOT }, Ordering::Relaxed);
+        }
+
+        Self {
+            slots,
+            state: SyncPoolState {
+                id:      PoolId::next(),
+                free:    AtomicU64::new(if cap > 0 { 0 } else { NO_SLOT as u64 }),
+                len:     AtomicUsize::new(0),
+                last:    AtomicUsize::new(0),
+                retired: AtomicUsize::new(0),
+            },
+            _phantom: Default::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn insert(&self, v: T) -> Option<SyncStrongRef<'_, T>> { self.try_insert(v).ok() }
+
+    /// Inserts an element, if the pool is full the element is returned in the error
+    pub fn try_insert(&self, v: T) -> Result<SyncStrongRef<'_, T>, InsertError<T>> {
+        let Some(index) = self.state.pop(self.slots()) else {
+            return Err(InsertError { value: v });
+        };
+
+        // The slot isn't live so no other thread can access it until it's published below
+        let slot = &self.slots()[index];
+        unsafe { *slot.elem.get() = Some(v) };
+        let g = generation(slot.state.load(Ordering::Relaxed));
+        slot.state.store(pack(g, true, 1), Ordering::Release);
+        self.state.len.fetch_add(1, Ordering::Relaxed);
+        self.state.last.fetch_max(index + 1, Ordering::Relaxed);
+
+        Ok(SyncStrongRef {
+            slot,
+            pool: &self.state,
+            index: index as u32,
+        })
+    }
+
+    /// Returns a strong reference to the element at `index`, or `None` if the slot is empty or its element is being
+    /// removed by another thread
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<SyncStrongRef<'_, T>> {
+        let slot = self.slots().get(index)?;
+
+        slot.acquire(None).then(|| SyncStrongRef {
+            slot,
+            pool: &self.state,
+            index: index as u32,
+        })
+    }
+
+    /// Returns a weak reference to the element at `index` if the slot holds one at the time of the call
+    #[must_use]
+    pub fn get_weak(&self, index: usize) -> Option<SyncWeakRef<'_, T>> {
+        let slot = self.slots().get(index)?;
+        let state = slot.state.load(Ordering::Relaxed);
+
+        is_live(state).then(|| SyncWeakRef {
+            slot,
+            pool: &self.state,
+            index: index as u32,
+            version: generation(state),
+        })
+    }
+
+    /// Returns true if the slot at `index` holds an element at the time of the call
+    #[must_use]
+    pub fn contains_index(&self, index: usize) -> bool { self.get_weak(index).is_some() }
+
+    /// Looks up the element a key was made for, `None` once any thread removed it
+    #[must_use]
+    pub fn get_by_key(&self, key: PoolKey) -> Option<SyncWeakRef<'_, T>> {
+        self.get_weak(key.index()).filter(|r| r.version == key.version())
+    }
+
+    /// Like [`SyncRcPool::get_by_key`] but keeps the element alive, so a concurrent removal can't drop it while the
+    /// reference exists
+    #[must_use]
+    pub fn upgrade_key(&self, key: PoolKey) -> Option<SyncStrongRef<'_, T>> {
+        self.get_by_key(key).and_then(|r| r.get())
+    }
+
+    /// Iterates over the elements in slot order, elements inserted or removed by other threads while iterating may
+    /// or may not be included
+    pub fn iter(&self) -> impl Iterator<Item = SyncStrongRef<'_, T>> {
+        (0..self.state.last.load(Ordering::Relaxed)).filter_map(|i| self.get(i))
+    }
+
+    /// Removes the element, upgrading weak references to it fails from now on in every thread. The element is dropped
+    /// by whichever thread releases the last strong reference to it, which can be this one. Removing an element that
+    /// another thread already removed does nothing.
+    ///
+    /// # Panics
+    ///
+    /// If `r` belongs to another pool.
+    pub fn remove(&self, r: &SyncWeakRef<T>) {
+        if r.pool.id != self.state.id {
+            panic!("Reference to an element of pool {} used with pool {}!", r.pool.id, self.state.id)
+        }
+
+        self.state.remove(r.index, r.slot, r.version)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize { self.state.len.load(Ordering::Relaxed) }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    #[must_use]
+    pub fn capacity(&self) -> usize { self.slots.as_ref().len().min(Self::MAX_CAPACITY) }
+
+    #[must_use]
+    pub fn id(&self) -> PoolId { self.state.id }
+
+    /// Number of slots taken out of use for good, each one was reused until its generation reached the largest
+    /// value a key can hold
+    #[must_use]
+    pub fn retired(&self) -> usize { self.state.retired.load(Ordering::Relaxed) }
+
+    #[must_use]
+    fn slots(&self) -> &[SyncSlot<T>] { &self.slots.as_ref()[..self.capacity()] }
+}
+
+impl<T: Debug, A: SyncSlotStorage<T>> Debug for SyncRcPool<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elements: Vec<_> = self.iter().collect();
+
+        f.debug_struct("SyncRcPool")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("elements", &elements)
+            .finish()
+    }
+}
+
+#[test]
+fn test_sync_pool() {
+    let pool = VecSyncRcPool::new_vec(2);
+    let a = pool.insert(1).unwrap();
+    let w = a.weak();
+    assert_eq!(*w.get().unwrap(), 1);
+
+    pool.remove(&w);
+    pool.remove(&w);
+    assert!(w.get().is_none());
+    assert_eq!(*a, 1);
+
+    // The slot is reused once the last reference is dropped, stale references stay invalid
+    let b = pool.insert(2).unwrap();
+    assert!(pool.insert(3).is_none());
+    drop(a);
+    let c = pool.insert(3).unwrap();
+    assert_eq!(c.index(), w.index());
+    assert!(w.get().is_none());
+    assert!(pool.upgrade_key(w.key()).is_none());
+    assert_eq!(*pool.upgrade_key(c.key()).unwrap(), 3);
+    assert_eq!(pool.iter().map(|r| *r).collect::<Vec<_>>(), [3, 2]);
+    drop(b);
+}
+
+#[test]
+fn test_sync_pool_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SyncStrongRef<'_, String>>();
+    assert_send_sync::<SyncWeakRef<'_, String>>();
+    assert_send_sync::<VecSyncRcPool<String>>();
+
+    let pool = VecSyncRcPool::new_vec(64);
+
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let pool = &pool;
+
+            s.spawn(move || {
+                let mut stale = Vec::new();
+
+                for i in 0..2000 {
+                    let r = pool.insert((t, i)).unwrap();
+                    let w = r.weak();
+                    assert_eq!(*w.get().unwrap(), (t, i));
+
+                    if i % 2 == 0 {
+                        pool.remove(&w);
+                        drop(r);
+                    } else {
+                        drop(r);
+                        pool.remove(&w);
+                    }
+
+                    assert!(w.get().is_none());
+                    stale.push(w);
+                }
+
+                assert!(stale.iter().all(|w| w.get().is_none()));
+            });
+        }
+    });
+
+    assert!(pool.is_empty());
+    assert_eq!(pool.iter().count(), 0);
+}